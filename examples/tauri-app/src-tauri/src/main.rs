@@ -5,7 +5,7 @@
 
 use serde::{Serialize, Deserialize};
 
-use tauri_plugin_synced_state::{SyncState, SyncStateToml, PluginBuilder};
+use tauri_plugin_synced_state::{SyncStateToml, PluginBuilder, Access};
 
 use ts_rs::TS;
 
@@ -24,23 +24,8 @@ impl Default for Count {
     }
 }
 
-#[tauri::command]
-async fn get_count(count: SyncState<'_, Count>) -> Result<Count, ()> {
-    Ok(count.get().await)
-}
-
-#[tauri::command]
-async fn get_count_toml(count: SyncStateToml<'_, Count>) -> Result<Count, ()> {
-    Ok(count.get().await)
-}
-
-#[tauri::command]
-async fn plus_count(count: SyncState<'_, Count>) -> Result<(), ()> {
-    
-    count.mutate(|count| count.count += 1 ).await;
-
-    Ok(())
-}
+// `count` is written by the frontend through the plugin's `set` and `reset` commands.
+// `count_toml` is only exposed for reading, so the frontend changes it through these commands instead.
 
 #[tauri::command]
 async fn plus_count_toml(count: SyncStateToml<'_, Count>) -> Result<(), ()> {
@@ -50,14 +35,6 @@ async fn plus_count_toml(count: SyncStateToml<'_, Count>) -> Result<(), ()> {
     Ok(())
 }
 
-#[tauri::command]
-async fn reset_count(count: SyncState<'_, Count>) -> Result<(), ()> {
-    
-    count.mutate(|count| *count = Count::default() ).await;
-
-    Ok(())
-}
-
 #[tauri::command]
 async fn reset_count_toml(count: SyncStateToml<'_, Count>) -> Result<(), ()> {
 
@@ -71,13 +48,11 @@ fn main() {
         .plugin(PluginBuilder::new()
             .manage::<Count>("count")
             .manage_toml::<Count>("count_toml", "count.toml")
+            .expose("count", Access::ReadWrite)
+            .expose("count_toml", Access::Read)
             .build()
         )
         .invoke_handler(tauri::generate_handler![
-            get_count,
-            plus_count,
-            reset_count,
-            get_count_toml,
             plus_count_toml,
             reset_count_toml,
         ])
@@ -4,12 +4,22 @@ import { defineStore } from "pinia";
 import { ref } from "vue";
 import { Count } from "../../src-tauri/bindings/Count"
 
+type Versioned<T> = { key: string, version: number, value: T }
+
 export const useCountStore = defineStore('count', () => {
     const count = ref<Count | null>(null);
 
     const init = async () => {
-        count.value = await invoke<Count>('get_count')
-        listen<Count>('synced-state://count-update', (event) => count.value = event.payload )
+        let version = -1
+        const apply = (update: Versioned<Count>) => {
+            if (update.version > version) {
+                version = update.version
+                count.value = update.value
+            }
+        }
+
+        await listen<Versioned<Count>>('synced-state://count-update', (event) => apply(event.payload))
+        apply(await invoke<Versioned<Count>>('plugin:synced_state|get_with_version', { key: 'count' }))
     }
 
     return { count, init }
@@ -4,12 +4,22 @@ import { defineStore } from "pinia";
 import { ref } from "vue";
 import { Count } from "../../src-tauri/bindings/Count"
 
+type Versioned<T> = { key: string, version: number, value: T }
+
 export const useCountTomlStore = defineStore('count_toml', () => {
     const countToml = ref<Count | null>(null);
 
     const init = async () => {
-        countToml.value = await invoke<Count>('get_count_toml')
-        listen<Count>('synced-state://count_toml-update', (event) => countToml.value = event.payload )
+        let version = -1
+        const apply = (update: Versioned<Count>) => {
+            if (update.version > version) {
+                version = update.version
+                countToml.value = update.value
+            }
+        }
+
+        await listen<Versioned<Count>>('synced-state://count_toml-update', (event) => apply(event.payload))
+        apply(await invoke<Versioned<Count>>('plugin:synced_state|get_with_version', { key: 'count_toml' }))
     }
 
     return { countToml, init }
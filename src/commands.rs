@@ -0,0 +1,75 @@
+use serde_json::Value;
+use tauri::{State, AppHandle};
+
+use crate::{registry::StateRegistry, deltas::Deltas, synced_state::Versioned, saveable_state::LoadReport};
+
+#[tauri::command]
+pub(crate) async fn get(registry: State<'_, StateRegistry>, key: String) -> Result<Value, String> {
+    registry.check_read(&key).map_err(|error| format!("{error:#}"))?;
+    registry.value(&key).await.map_err(|error| format!("{error:#}"))
+}
+
+/// Reads the value along with its version, so updates received while waiting for it can be told apart
+#[tauri::command]
+pub(crate) async fn get_with_version(registry: State<'_, StateRegistry>, key: String) -> Result<Versioned<Value>, String> {
+    registry.check_read(&key).map_err(|error| format!("{error:#}"))?;
+    registry.versioned(&key).await.map_err(|error| format!("{error:#}"))
+}
+
+/// The current value when it is newer than `since_version`, for listeners that missed updates
+#[tauri::command]
+pub(crate) async fn resync(registry: State<'_, StateRegistry>, key: String, since_version: u64) -> Result<Option<Versioned<Value>>, String> {
+    registry.check_read(&key).map_err(|error| format!("{error:#}"))?;
+
+    let current = registry.versioned(&key).await.map_err(|error| format!("{error:#}"))?;
+    Ok((current.version > since_version).then_some(current))
+}
+
+#[tauri::command]
+pub(crate) async fn set(registry: State<'_, StateRegistry>, key: String, value: Value) -> Result<(), String> {
+    registry.check_write(&key).map_err(|error| format!("{error:#}"))?;
+    registry.set_value(&key, value).await.map_err(|error| format!("{error:#}"))
+}
+
+#[tauri::command]
+pub(crate) async fn patch(registry: State<'_, StateRegistry>, key: String, partial: Value) -> Result<(), String> {
+    registry.check_write(&key).map_err(|error| format!("{error:#}"))?;
+    registry.patch(&key, partial).await.map_err(|error| format!("{error:#}"))
+}
+
+#[tauri::command]
+pub(crate) async fn reset(registry: State<'_, StateRegistry>, key: String) -> Result<(), String> {
+    registry.check_write(&key).map_err(|error| format!("{error:#}"))?;
+    registry.reset(&key).await.map_err(|error| format!("{error:#}"))
+}
+
+/// Emits the whole value of a state in delta mode, for listeners that lost track of its patches
+#[tauri::command]
+pub(crate) async fn snapshot(handle: AppHandle, registry: State<'_, StateRegistry>, deltas: State<'_, Deltas>, key: String) -> Result<(), String> {
+    registry.check_read(&key).map_err(|error| format!("{error:#}"))?;
+    let current = registry.versioned(&key).await.map_err(|error| format!("{error:#}"))?;
+    deltas.snapshot(&handle, &key, current).map_err(|error| format!("{error:#}"))
+}
+
+// Setup emits `-load-error`, `-lock-error` and `persistence-disabled` before any window listens,
+// so these commands return the same outcomes once the frontend asks for them
+
+/// How a file state was loaded, including the error it was recovered from
+#[tauri::command]
+pub(crate) async fn load_report(registry: State<'_, StateRegistry>, key: String) -> Result<Option<LoadReport>, String> {
+    registry.check_read(&key).map_err(|error| format!("{error:#}"))?;
+    registry.load_report(&key).await.map_err(|error| format!("{error:#}"))
+}
+
+/// Whether another instance locked the state's file
+#[tauri::command]
+pub(crate) async fn is_read_only(registry: State<'_, StateRegistry>, key: String) -> Result<bool, String> {
+    registry.check_read(&key).map_err(|error| format!("{error:#}"))?;
+    registry.is_read_only(&key).await.map_err(|error| format!("{error:#}"))
+}
+
+/// Why state changes are not being saved at all
+#[tauri::command]
+pub(crate) fn persistence_disabled(handle: AppHandle) -> Option<String> {
+    crate::persistence_disabled(&handle)
+}
@@ -0,0 +1,245 @@
+use std::{collections::HashMap, sync::Mutex};
+
+use anyhow::{Result, Context, bail};
+use json_patch::Patch;
+use serde::{Serialize, Deserialize};
+use serde_json::Value;
+use tauri::{AppHandle, Manager};
+
+use crate::synced_state::Versioned;
+
+/// Emits mutations of a state as RFC 6902 JSON Patch operations on `synced-state://<key>-patch`
+/// instead of the whole value on `synced-state://<key>-update`. Patches carry the version they produce.
+#[derive(Clone, Debug, Default)]
+pub struct DeltaUpdates {
+    snapshot_every: Option<usize>
+}
+
+impl DeltaUpdates {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Emits the whole value on `synced-state://<key>-update` after every `count` patches,
+    /// so listeners that missed one recover
+    pub fn snapshot_every(mut self, count: usize) -> Self {
+        self.snapshot_every = Some(count);
+        self
+    }
+}
+
+/// Payload of `synced-state://<key>-patch` events
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct VersionedPatch {
+    pub key: String,
+    pub version: u64,
+    pub patch: Patch
+}
+
+#[derive(Default)]
+struct Tracked {
+    config: DeltaUpdates,
+    previous: Option<Versioned<Value>>,
+    since_snapshot: usize
+}
+
+/// Last emitted value of every state in delta mode, managed as app state
+pub(crate) struct Deltas {
+    states: HashMap<String, Mutex<Tracked>>
+}
+
+impl Deltas {
+    pub fn new(configs: HashMap<String, DeltaUpdates>) -> Self {
+        let states = configs
+            .into_iter()
+            .map(|(key, config)| (key, Mutex::new(Tracked { config, ..Tracked::default() })))
+            .collect();
+
+        Self { states }
+    }
+
+    /// Emits the change from the last emitted value, returns false when the state isn't in delta mode
+    pub fn emit(&self, handle: &AppHandle, key: &str, version: u64, payload: &impl Serialize) -> bool {
+        let Some(tracked) = self.states.get(key) else { return false };
+        let Ok(mut tracked) = tracked.lock() else { return false };
+
+        let value = match serde_json::to_value(payload) {
+            Ok(value) => value,
+            Err(error) => {
+                eprintln!("Failed to diff '{key}' state: {error}");
+                return false;
+            },
+        };
+
+        let current = Versioned { key: key.to_owned(), version, value };
+
+        let Some(previous) = tracked.previous.replace(current.clone()) else {
+            // Listeners have nothing to apply a patch to before the first snapshot
+            emit_snapshot(handle, &current);
+            return true;
+        };
+
+        // Sent even when empty, so versions stay contiguous
+        let patch = VersionedPatch {
+            key: key.to_owned(),
+            version,
+            patch: json_patch::diff(&previous.value, &current.value)
+        };
+
+        handle
+            .emit_all(format!("synced-state://{key}-patch").as_str(), patch)
+            .ok();
+
+        tracked.since_snapshot += 1;
+
+        if tracked.config.snapshot_every.is_some_and(|every| tracked.since_snapshot >= every) {
+            tracked.since_snapshot = 0;
+            emit_snapshot(handle, &current);
+        }
+
+        true
+    }
+
+    /// Emits the last emitted value in full, ordered with the patches around it.
+    /// `current` is used when nothing was emitted yet.
+    pub fn snapshot(&self, handle: &AppHandle, key: &str, current: Versioned<Value>) -> Result<()> {
+        let tracked = self.states
+            .get(key)
+            .with_context(|| format!("State '{key}' does not emit patches"))?;
+
+        let Ok(mut tracked) = tracked.lock() else { bail!("Patches of '{key}' state are poisoned") };
+
+        let snapshot = tracked.previous.get_or_insert(current);
+        emit_snapshot(handle, snapshot);
+
+        tracked.since_snapshot = 0;
+
+        Ok(())
+    }
+}
+
+fn emit_snapshot(handle: &AppHandle, snapshot: &Versioned<Value>) {
+    let key = &snapshot.key;
+
+    handle
+        .emit_all(format!("synced-state://{key}-update").as_str(), snapshot)
+        .ok();
+}
+
+/// Rebuilds a state from its `-update` snapshots and `-patch` events
+#[derive(Clone, Debug)]
+pub struct PatchApplier {
+    snapshot: Versioned<Value>
+}
+
+impl PatchApplier {
+    pub fn new(snapshot: Versioned<Value>) -> Self {
+        Self { snapshot }
+    }
+
+    /// Replaces the value unless it is older than the current one
+    pub fn snapshot(&mut self, snapshot: Versioned<Value>) {
+        if snapshot.version >= self.snapshot.version {
+            self.snapshot = snapshot;
+        }
+    }
+
+    /// Applies the next patch atomically. Patches already covered by a snapshot are skipped,
+    /// a gap in versions fails and calls for a resync.
+    pub fn apply(&mut self, patch: &VersionedPatch) -> Result<()> {
+        let expected = self.snapshot.version + 1;
+
+        if patch.version < expected {
+            return Ok(());
+        }
+
+        if patch.version > expected {
+            bail!("Missed updates of '{}' state between versions {} and {}", patch.key, self.snapshot.version, patch.version);
+        }
+
+        json_patch::patch(&mut self.snapshot.value, &patch.patch).context("Failed to apply patch")?;
+        self.snapshot.version = patch.version;
+
+        Ok(())
+    }
+
+    /// Applies the JSON payload of a `-patch` event
+    pub fn apply_payload(&mut self, payload: &str) -> Result<()> {
+        let patch: VersionedPatch = serde_json::from_str(payload).context("Malformed patch")?;
+        self.apply(&patch)
+    }
+
+    pub fn version(&self) -> u64 {
+        self.snapshot.version
+    }
+
+    pub fn value(&self) -> &Value {
+        &self.snapshot.value
+    }
+
+    pub fn state<T>(&self) -> Result<T>
+    where T: for<'a> Deserialize<'a>
+    {
+        Ok(serde_json::from_value(self.snapshot.value.clone())?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn versioned(version: u64, value: Value) -> Versioned<Value> {
+        Versioned { key: String::from("settings"), version, value }
+    }
+
+    fn patch(version: u64, from: Value, to: Value) -> VersionedPatch {
+        VersionedPatch { key: String::from("settings"), version, patch: json_patch::diff(&from, &to) }
+    }
+
+    #[test]
+    fn applies_patches_in_order() {
+        let mut applier = PatchApplier::new(versioned(1, json!({ "volume": 1 })));
+
+        applier.apply(&patch(2, json!({ "volume": 1 }), json!({ "volume": 2 }))).unwrap();
+        applier.apply(&patch(3, json!({ "volume": 2 }), json!({ "volume": 2, "theme": "dark" }))).unwrap();
+
+        assert_eq!(applier.version(), 3);
+        assert_eq!(applier.value(), &json!({ "volume": 2, "theme": "dark" }));
+    }
+
+    #[test]
+    fn skips_stale_patches() {
+        let mut applier = PatchApplier::new(versioned(3, json!({ "volume": 3 })));
+
+        applier.apply(&patch(3, json!({ "volume": 2 }), json!({ "volume": 3 }))).unwrap();
+        applier.apply(&patch(2, json!({ "volume": 1 }), json!({ "volume": 2 }))).unwrap();
+
+        assert_eq!(applier.version(), 3);
+        assert_eq!(applier.value(), &json!({ "volume": 3 }));
+    }
+
+    #[test]
+    fn fails_on_a_version_gap() {
+        let mut applier = PatchApplier::new(versioned(1, json!({ "volume": 1 })));
+
+        assert!(applier.apply(&patch(3, json!({ "volume": 2 }), json!({ "volume": 3 }))).is_err());
+
+        assert_eq!(applier.version(), 1);
+        assert_eq!(applier.value(), &json!({ "volume": 1 }));
+    }
+
+    #[test]
+    fn replaces_the_value_with_newer_snapshots_only() {
+        let mut applier = PatchApplier::new(versioned(2, json!({ "volume": 2 })));
+
+        applier.snapshot(versioned(1, json!({ "volume": 1 })));
+        assert_eq!(applier.value(), &json!({ "volume": 2 }));
+
+        applier.snapshot(versioned(5, json!({ "volume": 5 })));
+        applier.apply(&patch(6, json!({ "volume": 5 }), json!({ "volume": 6 }))).unwrap();
+
+        assert_eq!(applier.version(), 6);
+        assert_eq!(applier.value(), &json!({ "volume": 6 }));
+    }
+}
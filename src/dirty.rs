@@ -0,0 +1,56 @@
+use std::sync::{Arc, atomic::{AtomicU64, Ordering}};
+
+use tauri::{AppHandle, Manager};
+
+/// Tracks whether the in-memory value of a file state has been written to disk
+#[derive(Clone, Default)]
+pub(crate) struct DirtyFlag {
+    generation: Arc<AtomicU64>,
+    saved: Arc<AtomicU64>,
+    emitter: Option<(String, AppHandle)>
+}
+
+impl DirtyFlag {
+    pub fn new(key: impl Into<String>, handle: &AppHandle) -> Self {
+        Self {
+            emitter: Some((key.into(), handle.clone())),
+            ..Self::default()
+        }
+    }
+
+    pub fn is_dirty(&self) -> bool {
+        self.generation.load(Ordering::SeqCst) != self.saved.load(Ordering::SeqCst)
+    }
+
+    pub fn generation(&self) -> u64 {
+        self.generation.load(Ordering::SeqCst)
+    }
+
+    pub fn changed(&self) -> u64 {
+        let was_dirty = self.is_dirty();
+        let generation = self.generation.fetch_add(1, Ordering::SeqCst) + 1;
+
+        if !was_dirty {
+            self.emit(true);
+        }
+
+        generation
+    }
+
+    pub fn saved(&self, generation: u64) {
+        let previous = self.saved.fetch_max(generation, Ordering::SeqCst);
+
+        if previous < generation && !self.is_dirty() {
+            self.emit(false);
+        }
+    }
+
+    fn emit(&self, dirty: bool) {
+        let Some((key, handle)) = &self.emitter else { return };
+        let event = format!("synced-state://{key}-dirty");
+
+        handle
+            .emit_all(event.as_str(), dirty)
+            .ok();
+    }
+}
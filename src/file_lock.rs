@@ -0,0 +1,133 @@
+use std::{path::{Path, PathBuf}, fs::{File, OpenOptions, create_dir_all}, ffi::OsString, sync::Mutex};
+
+use anyhow::{Result, Context, bail};
+use fs4::FileExt;
+use serde_json::Value;
+
+use crate::options::{LockPolicy, FileOptions};
+
+/// Advisory lock on a `<name>.lock` file next to a state file, released on drop.
+/// The state file itself is replaced on every write, so it can't hold the lock.
+pub(crate) struct FileLock(File);
+
+impl FileLock {
+    fn lock_path(path: &Path) -> PathBuf {
+        let mut name = path.file_name()
+            .map(OsString::from)
+            .unwrap_or_default();
+        name.push(".lock");
+
+        path.with_file_name(name)
+    }
+
+    fn open(path: &Path) -> Result<File> {
+        let lock_path = Self::lock_path(path);
+
+        if let Some(parent) = lock_path.parent() {
+            create_dir_all(parent)?;
+        }
+
+        OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .open(&lock_path)
+            .with_context(|| format!("Failed to open lock file '{}'", lock_path.display()))
+    }
+
+    pub async fn shared(path: &Path) -> Result<Self> {
+        let path = PathBuf::from(path);
+
+        tokio::task::spawn_blocking(move || {
+            let file = Self::open(&path)?;
+            FileExt::lock_shared(&file)?;
+            Ok(Self(file))
+        }).await?
+    }
+
+    pub async fn exclusive(path: &Path) -> Result<Self> {
+        let path = PathBuf::from(path);
+
+        tokio::task::spawn_blocking(move || {
+            let file = Self::open(&path)?;
+            FileExt::lock_exclusive(&file)?;
+            Ok(Self(file))
+        }).await?
+    }
+
+    pub fn try_exclusive(path: &Path) -> Result<Option<Self>> {
+        let file = Self::open(path)?;
+
+        match FileExt::try_lock_exclusive(&file) {
+            Ok(()) => Ok(Some(Self(file))),
+            Err(_) => Ok(None),
+        }
+    }
+}
+
+/// Cross-process coordination shared by a file state and its background writer
+#[derive(Default)]
+pub(crate) struct StateLock {
+    policy: LockPolicy,
+    owner: Option<FileLock>,
+    base: Mutex<Option<Value>>
+}
+
+impl StateLock {
+    /// Lock files only work next to local files, so other backends are never locked
+    pub fn acquire<T>(path: &Path, options: &FileOptions<T>) -> Result<Self> {
+        let policy = match options.storage().is_local() {
+            true => options.lock_policy,
+            false => LockPolicy::None,
+        };
+
+        let owner = match policy {
+            LockPolicy::Exclusive => FileLock::try_exclusive(path)?,
+            LockPolicy::None | LockPolicy::SharedWithReload => None,
+        };
+
+        Ok(Self {
+            policy,
+            owner,
+            base: Mutex::new(None)
+        })
+    }
+
+    pub fn policy(&self) -> LockPolicy {
+        self.policy
+    }
+
+    /// Another instance holds the exclusive lock, so this one must not write
+    pub fn is_read_only(&self) -> bool {
+        self.policy == LockPolicy::Exclusive && self.owner.is_none()
+    }
+
+    pub async fn read(&self, path: &Path) -> Result<Option<FileLock>> {
+        match self.policy {
+            LockPolicy::SharedWithReload => Ok(Some(FileLock::shared(path).await?)),
+            LockPolicy::None | LockPolicy::Exclusive => Ok(None),
+        }
+    }
+
+    pub async fn write(&self, path: &Path) -> Result<Option<FileLock>> {
+        if self.is_read_only() {
+            bail!("'{}' is locked by another instance and was opened read-only", path.display());
+        }
+
+        match self.policy {
+            LockPolicy::SharedWithReload => Ok(Some(FileLock::exclusive(path).await?)),
+            LockPolicy::None | LockPolicy::Exclusive => Ok(None),
+        }
+    }
+
+    /// Value last read from or written to the file, used as the base of three-way merges
+    pub fn base(&self) -> Option<Value> {
+        self.base.lock().ok()?.clone()
+    }
+
+    pub fn set_base(&self, value: Value) {
+        if let Ok(mut base) = self.base.lock() {
+            *base = Some(value);
+        }
+    }
+}
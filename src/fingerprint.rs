@@ -0,0 +1,25 @@
+use std::{sync::{Arc, atomic::{AtomicU64, Ordering}}, collections::hash_map::DefaultHasher, hash::{Hash, Hasher}};
+
+/// Hash of the bytes last read from or written to a state file
+#[derive(Clone, Default)]
+pub(crate) struct Fingerprint(Arc<AtomicU64>);
+
+impl Fingerprint {
+    pub fn of(bytes: &[u8]) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        bytes.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::SeqCst)
+    }
+
+    pub fn set(&self, hash: u64) {
+        self.0.store(hash, Ordering::SeqCst);
+    }
+
+    pub fn matches(&self, bytes: &[u8]) -> bool {
+        self.get() == Self::of(bytes)
+    }
+}
@@ -0,0 +1,25 @@
+use serde::{Serialize, Deserialize};
+
+use anyhow::Result;
+
+use super::{Format, Header};
+
+pub struct Bincode;
+
+impl Format for Bincode {
+    const EXTENSION: &'static str = "bin";
+    const HEADER: Option<Header> = Some(Header { tag: *b"BINC", version: 1 });
+    const SELF_DESCRIBING: bool = false;
+
+    fn encode<T>(value: &T) -> Result<Vec<u8>>
+    where T: Serialize
+    {
+        Ok(bincode::serialize(value)?)
+    }
+
+    fn decode<T>(bytes: &[u8]) -> Result<T>
+    where T: for<'a> Deserialize<'a>
+    {
+        Ok(bincode::deserialize::<T>(bytes)?)
+    }
+}
@@ -0,0 +1,26 @@
+use serde::{Serialize, Deserialize};
+
+use anyhow::Result;
+
+use super::{Format, Header};
+
+pub struct Cbor;
+
+impl Format for Cbor {
+    const EXTENSION: &'static str = "cbor";
+    const HEADER: Option<Header> = Some(Header { tag: *b"CBOR", version: 1 });
+
+    fn encode<T>(value: &T) -> Result<Vec<u8>>
+    where T: Serialize
+    {
+        let mut bytes = Vec::new();
+        ciborium::ser::into_writer(value, &mut bytes)?;
+        Ok(bytes)
+    }
+
+    fn decode<T>(bytes: &[u8]) -> Result<T>
+    where T: for<'a> Deserialize<'a>
+    {
+        Ok(ciborium::de::from_reader::<T, _>(bytes)?)
+    }
+}
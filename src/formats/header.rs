@@ -0,0 +1,101 @@
+use anyhow::{Result, bail};
+
+use super::Format;
+
+const MAGIC: &[u8; 4] = b"SYST";
+const HEADER_LENGTH: usize = MAGIC.len() + 4 + 2;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Header {
+    pub tag: [u8; 4],
+    pub version: u16
+}
+
+pub(crate) fn wrap<F>(payload: Vec<u8>) -> Vec<u8>
+where F: Format
+{
+    let Some(header) = F::HEADER else { return payload };
+
+    let mut bytes = Vec::with_capacity(HEADER_LENGTH + payload.len());
+    bytes.extend_from_slice(MAGIC);
+    bytes.extend_from_slice(&header.tag);
+    bytes.extend_from_slice(&header.version.to_le_bytes());
+    bytes.extend_from_slice(&payload);
+    bytes
+}
+
+pub(crate) fn unwrap<F>(bytes: &[u8]) -> Result<&[u8]>
+where F: Format
+{
+    let Some(expected) = F::HEADER else { return Ok(bytes) };
+
+    if bytes.len() < HEADER_LENGTH || &bytes[..MAGIC.len()] != MAGIC {
+        bail!("Missing state file header, expected '{}' data", String::from_utf8_lossy(&expected.tag));
+    }
+
+    let tag = &bytes[4..8];
+    let version = u16::from_le_bytes([bytes[8], bytes[9]]);
+
+    if tag != expected.tag {
+        bail!(
+            "State file format mismatch: expected '{}', found '{}'",
+            String::from_utf8_lossy(&expected.tag),
+            String::from_utf8_lossy(tag)
+        );
+    }
+
+    if version != expected.version {
+        bail!(
+            "Unsupported '{}' state file version {version}, expected {}",
+            String::from_utf8_lossy(tag),
+            expected.version
+        );
+    }
+
+    Ok(&bytes[HEADER_LENGTH..])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::{Serialize, Deserialize};
+
+    /// Only its header matters here, so it has no encoding of its own
+    struct Tagged<const TAG: u32, const VERSION: u16>;
+
+    impl<const TAG: u32, const VERSION: u16> Format for Tagged<TAG, VERSION> {
+        const EXTENSION: &'static str = "bin";
+        const HEADER: Option<Header> = Some(Header { tag: TAG.to_be_bytes(), version: VERSION });
+
+        fn encode<T>(_value: &T) -> Result<Vec<u8>>
+        where T: Serialize
+        {
+            Ok(Vec::new())
+        }
+
+        fn decode<T>(_bytes: &[u8]) -> Result<T>
+        where T: for<'a> Deserialize<'a>
+        {
+            bail!("Nothing to decode")
+        }
+    }
+
+    const ONE: u32 = u32::from_be_bytes(*b"ONE_");
+    const TWO: u32 = u32::from_be_bytes(*b"TWO_");
+
+    #[test]
+    fn unwraps_its_own_header() {
+        let bytes = wrap::<Tagged<ONE, 1>>(b"payload".to_vec());
+
+        assert_eq!(unwrap::<Tagged<ONE, 1>>(&bytes).unwrap(), b"payload");
+    }
+
+    #[test]
+    fn rejects_mismatched_headers() {
+        let bytes = wrap::<Tagged<ONE, 1>>(b"payload".to_vec());
+
+        assert!(unwrap::<Tagged<TWO, 1>>(&bytes).is_err());
+        assert!(unwrap::<Tagged<ONE, 2>>(&bytes).is_err());
+        assert!(unwrap::<Tagged<ONE, 1>>(b"payload").is_err());
+    }
+}
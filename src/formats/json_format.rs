@@ -0,0 +1,23 @@
+use serde::{Serialize, Deserialize};
+
+use anyhow::Result;
+
+use super::Format;
+
+pub struct Json;
+
+impl Format for Json {
+    const EXTENSION: &'static str = "json";
+
+    fn encode<T>(value: &T) -> Result<Vec<u8>>
+    where T: Serialize
+    {
+        Ok(serde_json::to_vec_pretty(value)?)
+    }
+
+    fn decode<T>(bytes: &[u8]) -> Result<T>
+    where T: for<'a> Deserialize<'a>
+    {
+        Ok(serde_json::from_slice::<T>(bytes)?)
+    }
+}
@@ -0,0 +1,45 @@
+pub(crate) mod header;
+pub(crate) mod toml_format;
+#[cfg(feature = "json")]
+pub(crate) mod json_format;
+#[cfg(feature = "yaml")]
+pub(crate) mod yaml_format;
+#[cfg(feature = "ron")]
+pub(crate) mod ron_format;
+#[cfg(feature = "msgpack")]
+pub(crate) mod msgpack_format;
+#[cfg(feature = "bincode")]
+pub(crate) mod bincode_format;
+#[cfg(feature = "cbor")]
+pub(crate) mod cbor_format;
+
+use serde::{Serialize, Deserialize};
+
+use anyhow::Result;
+
+pub use header::Header;
+pub use toml_format::Toml;
+#[cfg(feature = "json")]
+pub use json_format::Json;
+#[cfg(feature = "yaml")]
+pub use yaml_format::Yaml;
+#[cfg(feature = "ron")]
+pub use ron_format::Ron;
+#[cfg(feature = "msgpack")]
+pub use msgpack_format::MessagePack;
+#[cfg(feature = "bincode")]
+pub use bincode_format::Bincode;
+#[cfg(feature = "cbor")]
+pub use cbor_format::Cbor;
+
+pub trait Format: Send + Sync + 'static {
+    const EXTENSION: &'static str;
+    const HEADER: Option<Header> = None;
+    const SELF_DESCRIBING: bool = true;
+
+    fn encode<T>(value: &T) -> Result<Vec<u8>>
+    where T: Serialize;
+
+    fn decode<T>(bytes: &[u8]) -> Result<T>
+    where T: for<'a> Deserialize<'a>;
+}
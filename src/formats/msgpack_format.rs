@@ -0,0 +1,24 @@
+use serde::{Serialize, Deserialize};
+
+use anyhow::Result;
+
+use super::{Format, Header};
+
+pub struct MessagePack;
+
+impl Format for MessagePack {
+    const EXTENSION: &'static str = "msgpack";
+    const HEADER: Option<Header> = Some(Header { tag: *b"MPCK", version: 1 });
+
+    fn encode<T>(value: &T) -> Result<Vec<u8>>
+    where T: Serialize
+    {
+        Ok(rmp_serde::to_vec_named(value)?)
+    }
+
+    fn decode<T>(bytes: &[u8]) -> Result<T>
+    where T: for<'a> Deserialize<'a>
+    {
+        Ok(rmp_serde::from_slice::<T>(bytes)?)
+    }
+}
@@ -0,0 +1,25 @@
+use serde::{Serialize, Deserialize};
+use ron::ser::PrettyConfig;
+
+use anyhow::Result;
+
+use super::Format;
+
+pub struct Ron;
+
+impl Format for Ron {
+    const EXTENSION: &'static str = "ron";
+
+    fn encode<T>(value: &T) -> Result<Vec<u8>>
+    where T: Serialize
+    {
+        let string = ron::ser::to_string_pretty(value, PrettyConfig::default())?;
+        Ok(string.into_bytes())
+    }
+
+    fn decode<T>(bytes: &[u8]) -> Result<T>
+    where T: for<'a> Deserialize<'a>
+    {
+        Ok(ron::de::from_bytes::<T>(bytes)?)
+    }
+}
@@ -0,0 +1,58 @@
+use serde::{Serialize, Deserialize};
+
+use anyhow::Result;
+
+use super::Format;
+
+pub struct Toml;
+
+impl Format for Toml {
+    const EXTENSION: &'static str = "toml";
+
+    fn encode<T>(value: &T) -> Result<Vec<u8>>
+    where T: Serialize
+    {
+        // Going through `toml::Value` writes tables after plain values, whatever order the fields come in
+        let value = toml::Value::try_from(value)?;
+        let string = toml::ser::to_string(&value)?;
+        Ok(string.into_bytes())
+    }
+
+    fn decode<T>(bytes: &[u8]) -> Result<T>
+    where T: for<'a> Deserialize<'a>
+    {
+        Ok(toml::de::from_slice::<T>(bytes)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::{Serialize, Deserialize};
+
+    use super::*;
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Appearance {
+        theme: String
+    }
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Settings {
+        volume: u8,
+        appearance: Appearance
+    }
+
+    #[test]
+    fn encodes_values_with_fields_after_tables() {
+        let settings = Settings { volume: 3, appearance: Appearance { theme: String::from("dark") } };
+
+        let mut value = serde_json::to_value(&settings).unwrap();
+        value["__version"] = serde_json::json!(2);
+
+        let bytes = Toml::encode(&value).unwrap();
+        let decoded: serde_json::Value = Toml::decode(&bytes).unwrap();
+
+        assert_eq!(decoded, value);
+        assert_eq!(serde_json::from_value::<Settings>(decoded).unwrap(), settings);
+    }
+}
@@ -0,0 +1,24 @@
+use serde::{Serialize, Deserialize};
+
+use anyhow::Result;
+
+use super::Format;
+
+pub struct Yaml;
+
+impl Format for Yaml {
+    const EXTENSION: &'static str = "yaml";
+
+    fn encode<T>(value: &T) -> Result<Vec<u8>>
+    where T: Serialize
+    {
+        let string = serde_yaml::to_string(value)?;
+        Ok(string.into_bytes())
+    }
+
+    fn decode<T>(bytes: &[u8]) -> Result<T>
+    where T: for<'a> Deserialize<'a>
+    {
+        Ok(serde_yaml::from_slice::<T>(bytes)?)
+    }
+}
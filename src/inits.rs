@@ -1,90 +1,168 @@
-use std::{marker::PhantomData, path::{PathBuf, Path}};
+use std::{marker::PhantomData, path::{PathBuf, Path}, ops::Not, any::TypeId};
 
 use serde::{Serialize, Deserialize};
 use tauri::{Manager, AppHandle};
 
-use crate::{synced_state::{Synced}, synced_state_toml::SyncedToml};
+use crate::{synced_state::{Synced}, synced_state_file::SyncedFile, formats::Format, options::FileOptions, registry::{StateRegistry, PlainState, FileState}};
 
 use anyhow::Result;
 
 pub(crate) trait StateManage {
-    fn manage(&self, app: &AppHandle);
+    fn key(&self) -> &str;
+
+    /// Type Tauri manages the state as, which it can only hold once
+    fn type_id(&self) -> TypeId;
+
+    fn manage(&self, app: &AppHandle) -> Result<()>;
 }
 
+/// Saves states with unsaved changes on exit
 pub(crate) trait StateSave {
     fn save(&self, handle: &AppHandle) -> Result<()>;
+
+    /// Saves without marking the state saved, returning what does once the write is committed
+    fn save_staged(&self, handle: &AppHandle) -> Result<Box<dyn FnOnce() + Send>>;
 }
 
-pub(crate) struct StateInit<T>
-where T: Default + Serialize + for<'a> Deserialize<'a> + Clone + Sync + Send + 'static
+pub(crate) struct StateInit<T, Tag = ()>
+where T: Default + Serialize + for<'a> Deserialize<'a> + Clone + Sync + Send + 'static, Tag: 'static
 {
     key: String,
-    phantom: PhantomData<T>
+    phantom: PhantomData<T>,
+    tag: PhantomData<fn() -> Tag>
 }
 
-impl<T> StateInit<T>
-where T: Default + Serialize + for<'a> Deserialize<'a> + Clone + Sync + Send + 'static
+impl<T, Tag> StateInit<T, Tag>
+where T: Default + Serialize + for<'a> Deserialize<'a> + Clone + Sync + Send + 'static, Tag: 'static
 {
     pub fn new(key: impl Into<String>) -> Self {
         Self {
             key: key.into(),
-            phantom: PhantomData
+            phantom: PhantomData,
+            tag: PhantomData
         }
     }
 }
 
-impl<T> StateManage for StateInit<T>
-where T: Default + Serialize + for<'a> Deserialize<'a> + Clone + Sync + Send + 'static
+impl<T, Tag> StateManage for StateInit<T, Tag>
+where T: Default + Serialize + for<'a> Deserialize<'a> + Clone + Sync + Send + 'static, Tag: 'static
 {
-    fn manage(&self, handle: &AppHandle) {
-        let state = Synced::<T>::init_sync(&self.key, handle);
+    fn key(&self) -> &str {
+        &self.key
+    }
+
+    fn type_id(&self) -> TypeId {
+        TypeId::of::<Synced<T, Tag>>()
+    }
+
+    fn manage(&self, handle: &AppHandle) -> Result<()> {
+        let state = Synced::<T, Tag>::init_sync(&self.key, handle);
+
+        #[cfg(feature = "ipc")]
+        if let Some(sync) = handle.try_state::<crate::instance_sync::InstanceSync>() {
+            sync.register(&self.key, std::sync::Arc::new(state.clone()));
+        }
+
+        handle
+            .state::<StateRegistry>()
+            .register(&state, std::sync::Arc::new(PlainState(state.clone())))?;
+
         handle.manage(state);
+        Ok(())
     }
 }
 
-#[derive(Clone)]
-pub struct StateTomlInit<T>
-where T: Default + Serialize + for<'a> Deserialize<'a> + Clone + Sync + Send + 'static
+pub struct StateFileInit<T, F, Tag = ()>
+where T: Default + Serialize + for<'a> Deserialize<'a> + Clone + Sync + Send + 'static, F: Format, Tag: 'static
 {
     key: String,
     path: PathBuf,
-    phantom: PhantomData<T>
+    options: FileOptions<T>,
+    phantom: PhantomData<(T, F)>,
+    tag: PhantomData<fn() -> Tag>
 }
 
-impl<T> StateTomlInit<T>
-where T: Default + Serialize + for<'a> Deserialize<'a> + Clone + Sync + Send + 'static
+impl<T, F, Tag> Clone for StateFileInit<T, F, Tag>
+where T: Default + Serialize + for<'a> Deserialize<'a> + Clone + Sync + Send + 'static, F: Format, Tag: 'static
+{
+    fn clone(&self) -> Self {
+        Self {
+            key: self.key.clone(),
+            path: self.path.clone(),
+            options: self.options.clone(),
+            phantom: PhantomData,
+            tag: PhantomData
+        }
+    }
+}
+
+impl<T, F, Tag> StateFileInit<T, F, Tag>
+where T: Default + Serialize + for<'a> Deserialize<'a> + Clone + Sync + Send + 'static, F: Format, Tag: 'static
 {
     pub fn new(
         key: impl Into<String>,
-        path: impl AsRef<Path>
+        path: impl AsRef<Path>,
+        options: FileOptions<T>
     ) -> Self {
         let path = path.as_ref();
         Self {
             key: key.into(),
             path: PathBuf::from(path),
-            phantom: PhantomData
+            options,
+            phantom: PhantomData,
+            tag: PhantomData
         }
     }
 }
 
-impl<T> StateManage for StateTomlInit<T>
-where T: Default + Serialize + for<'a> Deserialize<'a> + Clone + Sync + Send + 'static
+impl<T, F, Tag> StateManage for StateFileInit<T, F, Tag>
+where T: Default + Serialize + for<'a> Deserialize<'a> + Clone + Sync + Send + 'static, F: Format, Tag: 'static
 {
-    fn manage(&self, handle: &AppHandle) {
-        let state = SyncedToml::<T>::init_sync(
+    fn key(&self) -> &str {
+        &self.key
+    }
+
+    fn type_id(&self) -> TypeId {
+        TypeId::of::<SyncedFile<T, F, Tag>>()
+    }
+
+    fn manage(&self, handle: &AppHandle) -> Result<()> {
+        let state = SyncedFile::<T, F, Tag>::init_sync(
             &self.key,
             &self.path,
+            self.options.clone(),
             handle
-        );
+        )?;
+
+        handle
+            .state::<StateRegistry>()
+            .register(&state, std::sync::Arc::new(FileState(state.clone())))?;
+
         handle.manage(state);
+        Ok(())
     }
 }
 
-impl<T> StateSave for StateTomlInit<T>
-where T: Default + Serialize + for<'a> Deserialize<'a> + Clone + Sync + Send + 'static
+impl<T, F, Tag> StateSave for StateFileInit<T, F, Tag>
+where T: Default + Serialize + for<'a> Deserialize<'a> + Clone + Sync + Send + 'static, F: Format, Tag: 'static
 {
     fn save(&self, handle: &AppHandle) -> Result<()> {
-        let state = handle.state::<SyncedToml<T>>();
+        let state = handle.state::<SyncedFile<T, F, Tag>>();
+
+        if state.is_dirty_sync().not() {
+            return Ok(());
+        }
+
         state.save_sync()
     }
+
+    fn save_staged(&self, handle: &AppHandle) -> Result<Box<dyn FnOnce() + Send>> {
+        let state = handle.state::<SyncedFile<T, F, Tag>>();
+
+        if state.is_dirty_sync().not() {
+            return Ok(Box::new(|| {}));
+        }
+
+        Ok(Box::new(state.save_staged_sync()?))
+    }
 }
\ No newline at end of file
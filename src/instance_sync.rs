@@ -0,0 +1,471 @@
+use std::{collections::HashMap, sync::{Arc, Mutex, RwLock, atomic::{AtomicU64, Ordering}}, time::Duration, ops::Not};
+
+use serde::{Serialize, Deserialize};
+use serde_json::Value;
+use tokio::{io::{AsyncRead, AsyncWrite, AsyncWriteExt, AsyncBufReadExt, BufReader, split}, sync::mpsc, time::sleep};
+use anyhow::Result;
+
+use crate::{synced_state::Synced, storage::BoxFuture};
+
+type LineWriter = Box<dyn AsyncWrite + Send + Unpin>;
+type States = Arc<RwLock<HashMap<String, Arc<dyn RemoteState>>>>;
+/// Local changes per key that the hub has not ordered yet. Messages reaching this instance
+/// before they are acknowledged were ordered before them, so they are already superseded.
+type Pending = Arc<Mutex<HashMap<String, u64>>>;
+
+/// A state that can be updated by and snapshotted for other instances
+pub(crate) trait RemoteState: Send + Sync {
+    fn apply<'a>(&'a self, value: Value, superseded: &'a (dyn Fn() -> bool + Sync)) -> BoxFuture<'a, Result<()>>;
+    fn snapshot(&self) -> BoxFuture<'_, Result<Value>>;
+}
+
+impl<T, Tag> RemoteState for Synced<T, Tag>
+where T: Default + Serialize + for<'a> Deserialize<'a> + Clone + Send + Sync + 'static, Tag: 'static
+{
+    fn apply<'a>(&'a self, value: Value, superseded: &'a (dyn Fn() -> bool + Sync)) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            self.apply_remote(serde_json::from_value(value)?, superseded).await;
+            Ok(())
+        })
+    }
+
+    fn snapshot(&self) -> BoxFuture<'_, Result<Value>> {
+        Box::pin(async move {
+            Ok(serde_json::to_value(self.get().await)?)
+        })
+    }
+}
+
+/// Clients send messages without a sequence number, the hub numbers them per key
+/// and sends them to every client, the sender included, so all apply them in one order.
+/// The sender recognises its own messages by their origin and takes them as acknowledgements.
+#[derive(Serialize, Deserialize)]
+struct Message {
+    key: String,
+    value: Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    sequence: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    origin: Option<u32>
+}
+
+enum Event {
+    Publish(String, String),
+    Line(u64, String),
+    Closed(u64),
+    Accepted(u64, LineWriter)
+}
+
+/// Keeps states consistent between running instances of the same app.
+/// The first instance listens on a local socket and orders and relays messages for the others,
+/// which connect to it and take over when it exits.
+pub(crate) struct InstanceSync {
+    states: States,
+    pending: Pending,
+    events: mpsc::UnboundedSender<Event>
+}
+
+impl InstanceSync {
+    pub fn start(identifier: &str) -> Self {
+        let states = States::default();
+        let pending = Pending::default();
+        let (events, receiver) = mpsc::unbounded_channel();
+
+        tauri::async_runtime::spawn(
+            run(transport::name(identifier), states.clone(), pending.clone(), events.clone(), receiver)
+        );
+
+        Self { states, pending, events }
+    }
+
+    pub fn register(&self, key: &str, state: Arc<dyn RemoteState>) {
+        if let Ok(mut states) = self.states.write() {
+            states.insert(key.to_owned(), state);
+        }
+    }
+
+    pub fn publish(&self, key: &str, value: &impl Serialize) {
+        let message = serde_json::to_value(value).and_then(|value| {
+            serde_json::to_string(&Message { key: key.to_owned(), value, sequence: None, origin: Some(std::process::id()) })
+        });
+
+        match message {
+            // Counted right away, as the state is still locked by the change being published
+            Ok(line) => {
+                track(&self.pending, key);
+                self.events.send(Event::Publish(key.to_owned(), line)).ok();
+            },
+            Err(error) => eprintln!("Failed to publish '{key}' state to other instances: {error}"),
+        }
+    }
+}
+
+async fn run(
+    name: String,
+    states: States,
+    pending: Pending,
+    events: mpsc::UnboundedSender<Event>,
+    mut receiver: mpsc::UnboundedReceiver<Event>
+) {
+    let ids = Arc::new(AtomicU64::new(0));
+    let mut reported = false;
+
+    loop {
+        if let Ok(stream) = transport::connect(&name).await {
+            let id = ids.fetch_add(1, Ordering::SeqCst);
+            let writer = attach(stream, id, events.clone());
+            run_client(id, writer, &states, &pending, &mut receiver).await;
+            continue;
+        }
+
+        match transport::Listener::bind(&name) {
+            Ok(listener) => {
+                accept(listener, ids.clone(), events.clone());
+                run_hub(&states, &pending, &mut receiver).await;
+                return;
+            },
+            Err(error) => {
+                if reported.not() {
+                    eprintln!("Failed to connect to other instances on '{name}': {error}");
+                    reported = true;
+                }
+                sleep(Duration::from_millis(250)).await;
+            },
+        }
+    }
+}
+
+async fn run_client(
+    id: u64,
+    mut writer: LineWriter,
+    states: &States,
+    pending: &Pending,
+    receiver: &mut mpsc::UnboundedReceiver<Event>
+) {
+    let origin = std::process::id();
+    let mut applied: HashMap<String, u64> = HashMap::new();
+    // Sent over this connection but not acknowledged, so no longer pending once it closes
+    let mut sent: HashMap<String, u64> = HashMap::new();
+
+    while let Some(event) = receiver.recv().await {
+        match event {
+            Event::Publish(key, line) => {
+                if send(&mut writer, &line).await.is_err() {
+                    acknowledge(pending, &key, 1);
+                    break;
+                }
+
+                *sent.entry(key).or_default() += 1;
+            },
+            Event::Line(_, line) => {
+                let Some(message) = parse(&line) else { continue };
+                let sequence = message.sequence.unwrap_or_default();
+
+                if applied.get(&message.key).is_some_and(|last| sequence <= *last) {
+                    continue;
+                }
+
+                applied.insert(message.key.clone(), sequence);
+
+                // Already applied locally when it was made
+                if message.origin == Some(origin) {
+                    if let Some(count) = sent.get_mut(&message.key) {
+                        *count = count.saturating_sub(1);
+                    }
+                    acknowledge(pending, &message.key, 1);
+                    continue;
+                }
+
+                apply(states, pending, message).await;
+            },
+            Event::Closed(closed) if closed == id => break,
+            Event::Closed(_) | Event::Accepted(..) => {},
+        }
+    }
+
+    for (key, count) in sent {
+        acknowledge(pending, &key, count);
+    }
+}
+
+async fn run_hub(
+    states: &States,
+    pending: &Pending,
+    receiver: &mut mpsc::UnboundedReceiver<Event>
+) {
+    let mut peers: HashMap<u64, LineWriter> = HashMap::new();
+    let mut sequences: HashMap<String, u64> = HashMap::new();
+
+    while let Some(event) = receiver.recv().await {
+        match event {
+            // Already applied locally, and ordered from now on
+            Event::Publish(key, line) => {
+                acknowledge(pending, &key, 1);
+
+                let Some((_, line)) = parse(&line).and_then(|message| order(&mut sequences, message)) else { continue };
+                broadcast(&mut peers, &line).await;
+            },
+            Event::Line(_, line) => {
+                let Some((message, line)) = parse(&line).and_then(|message| order(&mut sequences, message)) else { continue };
+
+                broadcast(&mut peers, &line).await;
+                apply(states, pending, message).await;
+            },
+            Event::Closed(id) => { peers.remove(&id); },
+            Event::Accepted(id, mut writer) => {
+                let mut connected = true;
+
+                for line in snapshots(states, &sequences).await {
+                    connected = connected && send(&mut writer, &line).await.is_ok();
+                }
+
+                if connected {
+                    peers.insert(id, writer);
+                }
+            },
+        }
+    }
+}
+
+/// Gives the message the next sequence number of its key, returning it along with its line
+fn order(sequences: &mut HashMap<String, u64>, mut message: Message) -> Option<(Message, String)> {
+    let sequence = sequences.entry(message.key.clone()).or_default();
+    *sequence += 1;
+    message.sequence = Some(*sequence);
+
+    match serde_json::to_string(&message) {
+        Ok(line) => Some((message, line)),
+        Err(error) => {
+            eprintln!("Failed to relay '{}' state to other instances: {error}", message.key);
+            None
+        },
+    }
+}
+
+fn track(pending: &Pending, key: &str) {
+    if let Ok(mut pending) = pending.lock() {
+        *pending.entry(key.to_owned()).or_default() += 1;
+    }
+}
+
+fn acknowledge(pending: &Pending, key: &str, count: u64) {
+    let Ok(mut pending) = pending.lock() else { return };
+    let Some(outstanding) = pending.get_mut(key) else { return };
+
+    *outstanding = outstanding.saturating_sub(count);
+
+    if *outstanding == 0 {
+        pending.remove(key);
+    }
+}
+
+fn is_pending(pending: &Pending, key: &str) -> bool {
+    pending
+        .lock()
+        .map(|pending| pending.contains_key(key))
+        .unwrap_or_default()
+}
+
+fn accept(
+    mut listener: transport::Listener,
+    ids: Arc<AtomicU64>,
+    events: mpsc::UnboundedSender<Event>
+) {
+    tauri::async_runtime::spawn(async move {
+        loop {
+            match listener.accept().await {
+                Ok(stream) => {
+                    let id = ids.fetch_add(1, Ordering::SeqCst);
+                    let writer = attach(stream, id, events.clone());
+
+                    if events.send(Event::Accepted(id, writer)).is_err() {
+                        return;
+                    }
+                },
+                Err(error) => {
+                    eprintln!("Stopped accepting other instances: {error}");
+                    return;
+                },
+            }
+        }
+    });
+}
+
+/// Forwards lines read from the stream as events, returning its write half
+fn attach<S>(stream: S, id: u64, events: mpsc::UnboundedSender<Event>) -> LineWriter
+where S: AsyncRead + AsyncWrite + Send + 'static
+{
+    let (reader, writer) = split(stream);
+
+    tauri::async_runtime::spawn(async move {
+        let mut lines = BufReader::new(reader).lines();
+
+        while let Ok(Some(line)) = lines.next_line().await {
+            if events.send(Event::Line(id, line)).is_err() {
+                return;
+            }
+        }
+
+        events.send(Event::Closed(id)).ok();
+    });
+
+    Box::new(writer)
+}
+
+async fn send(writer: &mut LineWriter, line: &str) -> std::io::Result<()> {
+    writer.write_all(line.as_bytes()).await?;
+    writer.write_all(b"\n").await?;
+    writer.flush().await
+}
+
+async fn broadcast(peers: &mut HashMap<u64, LineWriter>, line: &str) {
+    let mut disconnected = Vec::new();
+
+    for (id, writer) in peers.iter_mut() {
+        if send(writer, line).await.is_err() {
+            disconnected.push(*id);
+        }
+    }
+
+    for id in disconnected {
+        peers.remove(&id);
+    }
+}
+
+fn registered(states: &States) -> Vec<(String, Arc<dyn RemoteState>)> {
+    states
+        .read()
+        .map(|states| {
+            states
+                .iter()
+                .map(|(key, state)| (key.clone(), state.clone()))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn parse(line: &str) -> Option<Message> {
+    match serde_json::from_str::<Message>(line) {
+        Ok(message) => Some(message),
+        Err(error) => {
+            eprintln!("Ignoring malformed message from another instance: {error}");
+            None
+        },
+    }
+}
+
+async fn apply(states: &States, pending: &Pending, message: Message) {
+    let state = states
+        .read()
+        .ok()
+        .and_then(|states| states.get(&message.key).cloned());
+
+    let Some(state) = state else { return };
+
+    let superseded = || is_pending(pending, &message.key);
+
+    if let Err(error) = state.apply(message.value, &superseded).await {
+        eprintln!("Failed to apply '{}' state from another instance: {error}", message.key);
+    }
+}
+
+async fn snapshots(states: &States, sequences: &HashMap<String, u64>) -> Vec<String> {
+    let mut lines = Vec::new();
+
+    for (key, state) in registered(states) {
+        let sequence = sequences.get(&key).copied().unwrap_or_default();
+
+        let line = state.snapshot().await.and_then(|value| {
+            Ok(serde_json::to_string(&Message { key, value, sequence: Some(sequence), origin: None })?)
+        });
+
+        match line {
+            Ok(line) => lines.push(line),
+            Err(error) => eprintln!("Failed to snapshot state for another instance: {error}"),
+        }
+    }
+
+    lines
+}
+
+#[cfg(unix)]
+mod transport {
+    use std::{io, path::PathBuf};
+
+    use tokio::net::{UnixListener, UnixStream};
+
+    pub fn name(identifier: &str) -> String {
+        let dir = std::env::var_os("XDG_RUNTIME_DIR")
+            .map(PathBuf::from)
+            .unwrap_or_else(std::env::temp_dir);
+
+        dir.join(format!("{identifier}.synced-state.sock"))
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    pub async fn connect(name: &str) -> io::Result<UnixStream> {
+        UnixStream::connect(name).await
+    }
+
+    pub struct Listener(UnixListener);
+
+    impl Listener {
+        pub fn bind(name: &str) -> io::Result<Self> {
+            match UnixListener::bind(name) {
+                Err(error) if error.kind() == io::ErrorKind::AddrInUse => {
+                    // Another instance may have become the hub since we last tried to connect
+                    if std::os::unix::net::UnixStream::connect(name).is_ok() {
+                        return Err(error);
+                    }
+
+                    std::fs::remove_file(name)?;
+                    UnixListener::bind(name).map(Self)
+                },
+                result => result.map(Self),
+            }
+        }
+
+        pub async fn accept(&mut self) -> io::Result<UnixStream> {
+            let (stream, _) = self.0.accept().await?;
+            Ok(stream)
+        }
+    }
+}
+
+#[cfg(windows)]
+mod transport {
+    use std::io;
+
+    use tokio::net::windows::named_pipe::{ServerOptions, ClientOptions, NamedPipeServer, NamedPipeClient};
+
+    pub fn name(identifier: &str) -> String {
+        format!(r"\\.\pipe\{identifier}.synced-state")
+    }
+
+    pub async fn connect(name: &str) -> io::Result<NamedPipeClient> {
+        ClientOptions::new().open(name)
+    }
+
+    pub struct Listener {
+        name: String,
+        next: NamedPipeServer
+    }
+
+    impl Listener {
+        pub fn bind(name: &str) -> io::Result<Self> {
+            let next = ServerOptions::new()
+                .first_pipe_instance(true)
+                .create(name)?;
+
+            Ok(Self { name: name.to_owned(), next })
+        }
+
+        pub async fn accept(&mut self) -> io::Result<NamedPipeServer> {
+            self.next.connect().await?;
+
+            let next = ServerOptions::new().create(&self.name)?;
+            Ok(std::mem::replace(&mut self.next, next))
+        }
+    }
+}
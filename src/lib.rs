@@ -2,23 +2,80 @@ pub(crate) mod saveable_state;
 pub mod synced_state;
 pub(crate) mod utils;
 pub(crate) mod inits;
+pub(crate) mod options;
+pub(crate) mod migrations;
+pub(crate) mod writer;
+pub(crate) mod dirty;
+pub(crate) mod fingerprint;
+pub(crate) mod file_lock;
+pub(crate) mod portable;
+pub(crate) mod overrides;
+pub(crate) mod registry;
+pub(crate) mod commands;
+pub(crate) mod deltas;
+#[cfg(feature = "ipc")]
+pub(crate) mod instance_sync;
+#[cfg(feature = "watch")]
+pub(crate) mod watcher;
+pub mod synced_state_file;
 pub mod synced_state_toml;
+pub mod formats;
+pub mod storage;
 
-use std::path::Path;
+use std::{path::Path, collections::{HashMap, HashSet}, ops::Not};
 
-use inits::{StateManage, StateInit, StateSave, StateTomlInit};
+use inits::{StateManage, StateInit, StateSave, StateFileInit};
+use formats::{Format, Toml};
 use serde::{Serialize, Deserialize};
-pub use synced_state::Synced;
+pub use synced_state::{Synced, Versioned};
+pub use synced_state_file::SyncedFile;
 pub use synced_state_toml::SyncedToml;
-use tauri::{plugin::{self, TauriPlugin}, RunEvent, Wry, State};
+pub use saveable_state::{Saveable, SaveableToml, LoadReport};
+pub use options::{FileOptions, Durability, RecoveryPolicy, SavePolicy, LockPolicy, BaseDir};
+pub use migrations::{MigrationFn, NewerVersion};
+pub use portable::PortableMode;
+pub use overrides::OverrideSources;
+pub use registry::{Access, StateRegistry};
+pub use deltas::{DeltaUpdates, VersionedPatch, PatchApplier};
+use tauri::{plugin::{self, TauriPlugin}, RunEvent, Wry, State, Manager};
+#[cfg(any(feature = "sqlite", feature = "redb"))]
+use anyhow::Context;
 
 pub type SyncState<'a, T> = State<'a, Synced<T>>;
+pub type SyncStateFile<'a, T, F> = State<'a, SyncedFile<T, F>>;
 pub type SyncStateToml<'a, T> = State<'a, SyncedToml<T>>;
+pub type SyncStateKeyed<'a, T, Tag> = State<'a, Synced<T, Tag>>;
+pub type SyncStateFileKeyed<'a, T, F, Tag> = State<'a, SyncedFile<T, F, Tag>>;
+pub type SyncStateTomlKeyed<'a, T, Tag> = State<'a, SyncedToml<T, Tag>>;
+
+#[cfg(feature = "sqlite")]
+const DATABASE_FILE: &str = "synced-state.db";
+#[cfg(feature = "redb")]
+const REDB_FILE: &str = "synced-state.redb";
 
 
 pub struct PluginBuilder {
     states_manage: Vec<Box<dyn StateManage + Sync + Send>>,
-    states_save: Vec<Box<dyn StateSave + Sync + Send>>
+    states_save: Vec<Box<dyn StateSave + Sync + Send>>,
+    #[cfg(feature = "sqlite")]
+    sqlite_states_save: Vec<Box<dyn StateSave + Sync + Send>>,
+    #[cfg(feature = "ipc")]
+    sync_instances: bool,
+    #[cfg(feature = "sqlite")]
+    database: Option<storage::SqliteDatabase>,
+    #[cfg(feature = "redb")]
+    redb: Option<storage::RedbDatabase>,
+    portable: Option<PortableMode>,
+    overrides: Option<OverrideSources>,
+    access: HashMap<String, Access>,
+    deltas: HashMap<String, DeltaUpdates>,
+    errors: Vec<String>
+}
+
+impl Default for PluginBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl PluginBuilder {
@@ -27,13 +84,33 @@ impl PluginBuilder {
         Self {
             states_manage: Vec::new(),
             states_save: Vec::new(),
+            #[cfg(feature = "sqlite")]
+            sqlite_states_save: Vec::new(),
+            #[cfg(feature = "ipc")]
+            sync_instances: false,
+            #[cfg(feature = "sqlite")]
+            database: None,
+            #[cfg(feature = "redb")]
+            redb: None,
+            portable: None,
+            overrides: None,
+            access: HashMap::new(),
+            deltas: HashMap::new(),
+            errors: Vec::new()
         }
     }
 
-    pub fn manage<T>(mut self, key: impl Into<String>) -> Self
+    pub fn manage<T>(self, key: impl Into<String>) -> Self
     where T: Default + Serialize + for<'a> Deserialize<'a> + Clone + Sync + Send + 'static
-    {   
-        let state = StateInit::<T>::new(key);
+    {
+        self.manage_keyed::<T, ()>(key)
+    }
+
+    /// Manages a state as `Synced<T, Tag>`, so several states can share the type `T`
+    pub fn manage_keyed<T, Tag>(mut self, key: impl Into<String>) -> Self
+    where T: Default + Serialize + for<'a> Deserialize<'a> + Clone + Sync + Send + 'static, Tag: 'static
+    {
+        let state = StateInit::<T, Tag>::new(key);
         self.states_manage.push(
             Box::new(state)
         );
@@ -41,13 +118,67 @@ impl PluginBuilder {
     }
 
     pub fn manage_toml<T>(
-        mut self,
+        self,
+        key: impl Into<String>,
+        path: impl AsRef<Path>,
+    ) -> Self
+    where T: Default + Serialize + for<'a> Deserialize<'a> + Clone + Sync + Send + 'static
+    {
+        self.manage_file::<T, Toml>(key, path)
+    }
+
+    pub fn manage_toml_keyed<T, Tag>(
+        self,
+        key: impl Into<String>,
+        path: impl AsRef<Path>,
+    ) -> Self
+    where T: Default + Serialize + for<'a> Deserialize<'a> + Clone + Sync + Send + 'static, Tag: 'static
+    {
+        self.manage_file_keyed_with::<T, Toml, Tag>(key, path, FileOptions::default())
+    }
+
+    pub fn manage_toml_with<T>(
+        self,
         key: impl Into<String>,
         path: impl AsRef<Path>,
+        options: FileOptions<T>
     ) -> Self
     where T: Default + Serialize + for<'a> Deserialize<'a> + Clone + Sync + Send + 'static
-    {   
-        let state = StateTomlInit::<T>::new(key, path);
+    {
+        self.manage_file_with::<T, Toml>(key, path, options)
+    }
+
+    pub fn manage_file<T, F>(
+        self,
+        key: impl Into<String>,
+        path: impl AsRef<Path>,
+    ) -> Self
+    where T: Default + Serialize + for<'a> Deserialize<'a> + Clone + Sync + Send + 'static, F: Format
+    {
+        self.manage_file_with::<T, F>(key, path, FileOptions::default())
+    }
+
+    pub fn manage_file_with<T, F>(
+        self,
+        key: impl Into<String>,
+        path: impl AsRef<Path>,
+        options: FileOptions<T>
+    ) -> Self
+    where T: Default + Serialize + for<'a> Deserialize<'a> + Clone + Sync + Send + 'static, F: Format
+    {
+        self.manage_file_keyed_with::<T, F, ()>(key, path, options)
+    }
+
+    /// Manages a file state as `SyncedFile<T, F, Tag>`, so several states can share the type `T`
+    pub fn manage_file_keyed_with<T, F, Tag>(
+        mut self,
+        key: impl Into<String>,
+        path: impl AsRef<Path>,
+        options: FileOptions<T>
+    ) -> Self
+    where T: Default + Serialize + for<'a> Deserialize<'a> + Clone + Sync + Send + 'static, F: Format, Tag: 'static
+    {
+        let state = StateFileInit::<T, F, Tag>::new(key, path, options);
         self.states_manage.push(
             Box::new(state.clone())
         );
@@ -57,31 +188,264 @@ impl PluginBuilder {
         self
     }
 
+    /// Stores the state as a TOML row of the plugin's SQLite database in the app data directory
+    #[cfg(feature = "sqlite")]
+    pub fn manage_sqlite<T>(self, key: impl Into<String>) -> Self
+    where T: Default + Serialize + for<'a> Deserialize<'a> + Clone + Sync + Send + 'static
+    {
+        self.manage_sqlite_with::<T, Toml>(key, FileOptions::default())
+    }
+
+    #[cfg(feature = "sqlite")]
+    pub fn manage_sqlite_with<T, F>(
+        mut self,
+        key: impl Into<String>,
+        options: FileOptions<T>
+    ) -> Self
+    where T: Default + Serialize + for<'a> Deserialize<'a> + Clone + Sync + Send + 'static, F: Format
+    {
+        let key: String = key.into();
+
+        let database = self.database
+            .get_or_insert_with(storage::SqliteDatabase::new)
+            .backend::<F>(options.schema_version());
+
+        // Saved apart from the other states, in one transaction on exit
+        let state = StateFileInit::<T, F>::new(key.clone(), key, options.backend(database));
+        self.states_manage.push(
+            Box::new(state.clone())
+        );
+        self.sqlite_states_save.push(
+            Box::new(state)
+        );
+        self
+    }
+
+    /// Stores the state as TOML in the plugin's redb database in the app data directory
+    #[cfg(feature = "redb")]
+    pub fn manage_redb<T>(self, key: impl Into<String>) -> Self
+    where T: Default + Serialize + for<'a> Deserialize<'a> + Clone + Sync + Send + 'static
+    {
+        self.manage_redb_with::<T, Toml>(key, storage::RedbLayout::default(), FileOptions::default())
+    }
+
+    #[cfg(feature = "redb")]
+    pub fn manage_redb_with<T, F>(
+        mut self,
+        key: impl Into<String>,
+        layout: storage::RedbLayout,
+        options: FileOptions<T>
+    ) -> Self
+    where T: Default + Serialize + for<'a> Deserialize<'a> + Clone + Sync + Send + 'static, F: Format
+    {
+        let key: String = key.into();
+
+        // Fields are split by decoding the stored state as a generic value
+        if layout == storage::RedbLayout::PerField && F::SELF_DESCRIBING.not() {
+            self.errors.push(format!("State '{key}' cannot be stored per field in the '{}' format", F::EXTENSION));
+        }
+
+        let database = self.redb
+            .get_or_insert_with(storage::RedbDatabase::new)
+            .backend::<F>(layout);
+
+        let options = options.backend(database);
+        self.manage_file_with::<T, F>(key.clone(), key, options)
+    }
+
+    /// Moves every file-system state next to the executable when portable mode is detected
+    pub fn portable(mut self, portable: PortableMode) -> Self {
+        self.portable = Some(portable);
+        self
+    }
+
+    /// Merges environment variable and command line overrides over file states at startup, without saving them
+    pub fn overrides(mut self, overrides: OverrideSources) -> Self {
+        self.overrides = Some(overrides);
+        self
+    }
+
+    /// Lets the frontend read and/or write the state through the plugin's `get`, `set`, `patch` and `reset` commands
+    pub fn expose(mut self, key: impl Into<String>, access: Access) -> Self {
+        self.access.insert(key.into(), access);
+        self
+    }
+
+    /// Emits mutations of the state as JSON Patch operations instead of whole values
+    pub fn delta_updates(mut self, key: impl Into<String>, deltas: DeltaUpdates) -> Self {
+        self.deltas.insert(key.into(), deltas);
+        self
+    }
+
+    /// Propagates mutations of `manage` states to other running instances of the app
+    #[cfg(feature = "ipc")]
+    pub fn sync_instances(mut self, sync_instances: bool) -> Self {
+        self.sync_instances = sync_instances;
+        self
+    }
+
+    /// Checks the states against each other, as far as is known before the app starts
+    fn validate(&self) -> anyhow::Result<()> {
+        if let Some(error) = self.errors.first() {
+            anyhow::bail!("{error}");
+        }
+
+        let mut keys = HashSet::new();
+        let mut types = HashMap::new();
+
+        for state in self.states_manage.iter() {
+            if keys.insert(state.key()).not() {
+                anyhow::bail!("State key '{}' is managed more than once", state.key());
+            }
+
+            if let Some(existing) = types.insert(state.type_id(), state.key()) {
+                anyhow::bail!("States '{existing}' and '{}' have the same type, give one of them a tag to manage both", state.key());
+            }
+        }
+
+        if let Some(key) = self.access.keys().find(|key| keys.contains(key.as_str()).not()) {
+            anyhow::bail!("State '{key}' is exposed but never managed");
+        }
+
+        if let Some(key) = self.deltas.keys().find(|key| keys.contains(key.as_str()).not()) {
+            anyhow::bail!("State '{key}' has delta updates but is never managed");
+        }
+
+        Ok(())
+    }
+
+    /// Builds the plugin, panicking when it was configured with invalid states
     pub fn build(self) -> TauriPlugin<Wry> {
-        plugin::Builder::new("synced_state")
+        self.try_build().unwrap_or_else(|error| panic!("{error}"))
+    }
+
+    /// Builds the plugin, failing when it was configured with invalid states
+    pub fn try_build(self) -> anyhow::Result<TauriPlugin<Wry>> {
+        self.validate()?;
+
+        #[cfg(feature = "sqlite")]
+        let database = self.database.clone();
+
+        let plugin = plugin::Builder::new("synced_state")
+            .invoke_handler(tauri::generate_handler![
+                commands::get,
+                commands::get_with_version,
+                commands::resync,
+                commands::set,
+                commands::patch,
+                commands::reset,
+                commands::snapshot,
+                commands::load_report,
+                commands::is_read_only,
+                commands::persistence_disabled
+            ])
             .setup(move |handle| {
 
-                self.states_manage.iter().for_each(|state| {
-                    state.manage(handle);
-                });
+                handle.manage(StateRegistry::new(self.access));
+                handle.manage(deltas::Deltas::new(self.deltas));
+
+                if let Some(portable) = self.portable.as_ref().map(PortableMode::detect).transpose()?.flatten() {
+                    // See `commands::persistence_disabled`
+                    if let Some(reason) = portable.disabled_reason() {
+                        eprintln!("{reason}, state changes will not be saved");
+
+                        handle
+                            .emit_all("synced-state://persistence-disabled", reason)
+                            .ok();
+                    }
+
+                    handle.manage(portable);
+                }
+
+                if let Some(overrides) = &self.overrides {
+                    handle.manage(overrides.collect());
+                }
+
+                #[cfg(feature = "sqlite")]
+                if let Some(database) = &self.database {
+                    database.open(database_dir(handle)?.join(DATABASE_FILE))?;
+                }
+
+                #[cfg(feature = "redb")]
+                if let Some(database) = &self.redb {
+                    database.open(database_dir(handle)?.join(REDB_FILE))?;
+                }
+
+                #[cfg(feature = "ipc")]
+                if self.sync_instances {
+                    let identifier = &handle.config().tauri.bundle.identifier;
+                    handle.manage(instance_sync::InstanceSync::start(identifier));
+                }
+
+                for state in self.states_manage.iter() {
+                    state.manage(handle)?;
+                }
 
                 Ok(())
             })
             .on_event(move |handle, event| {
 
                 let RunEvent::Exit = event else { return };
-                
-                // let states_save = self.states_save.cln
 
-                self.states_save.iter().for_each(|state| {
-                    let result = state.save(handle);
+                #[cfg(feature = "sqlite")]
+                if let Some(database) = &database {
+                    let mut committed = Vec::new();
+
+                    let saved = database.transaction(|| {
+                        for state in self.sqlite_states_save.iter() {
+                            committed.push(state.save_staged(handle)?);
+                        }
+                        Ok(())
+                    });
 
-                    if let Err(error) = result {
-                        eprintln!("Error while saving state: {error}")
+                    match saved {
+                        Ok(()) => committed.into_iter().for_each(|commit| commit()),
+                        Err(error) => eprintln!("Error while saving state: {error:#}"),
                     }
-                });
+                }
+
+                for state in self.states_save.iter() {
+                    if let Err(error) = state.save(handle) {
+                        eprintln!("Error while saving state: {error}");
+                    }
+                }
 
             })
-            .build()
+            .build();
+
+        Ok(plugin)
+    }
+}
+
+/// Why state changes are not being saved at all, when the portable directory is read-only
+pub fn persistence_disabled(handle: &tauri::AppHandle) -> Option<String> {
+    handle.try_state::<portable::Portable>()?.disabled_reason()
+}
+
+/// App data directory, or the portable directory when it is writable
+#[cfg(any(feature = "sqlite", feature = "redb"))]
+fn database_dir(handle: &tauri::AppHandle) -> anyhow::Result<std::path::PathBuf> {
+    match handle.try_state::<portable::Portable>() {
+        Some(portable) if !portable.read_only => Ok(portable.dir.clone()),
+        _ => handle.path_resolver()
+            .app_data_dir()
+            .context("Failed to resolve app data directory"),
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Other;
+
+    #[test]
+    fn rejects_conflicting_states() {
+        assert!(PluginBuilder::new().manage::<u32>("count").manage_keyed::<u32, Other>("other").validate().is_ok());
+
+        assert!(PluginBuilder::new().manage::<u32>("count").manage::<String>("count").validate().is_err());
+        assert!(PluginBuilder::new().manage::<u32>("count").manage::<u32>("other").validate().is_err());
+        assert!(PluginBuilder::new().manage::<u32>("count").expose("other", Access::Read).validate().is_err());
+        assert!(PluginBuilder::new().manage::<u32>("count").delta_updates("other", DeltaUpdates::default()).validate().is_err());
+    }
+}
@@ -0,0 +1,183 @@
+use std::{sync::Arc, fmt};
+
+use anyhow::{Result, bail, Context};
+use serde_json::Value;
+
+/// Reserved like `NULLS_KEY`, so it can't collide with a field of the state
+pub(crate) const VERSION_KEY: &str = "__version";
+/// Paths of the fields stripped as null, so loading doesn't fill them back in with defaults
+pub(crate) const NULLS_KEY: &str = "__nulls";
+
+pub type MigrationFn = Arc<dyn Fn(Value) -> Result<Value> + Send + Sync>;
+
+/// The file was written by a newer version of the app, so it must be left untouched
+#[derive(Debug)]
+pub struct NewerVersion {
+    pub found: u32,
+    pub supported: u32
+}
+
+impl fmt::Display for NewerVersion {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "State file version {} is newer than the supported version {}", self.found, self.supported)
+    }
+}
+
+impl std::error::Error for NewerVersion {}
+
+pub(crate) fn version_of(value: &Value) -> Result<u32> {
+    let Some(version) = value.get(VERSION_KEY) else { return Ok(0) };
+
+    let version = version
+        .as_u64()
+        .and_then(|version| u32::try_from(version).ok())
+        .with_context(|| format!("Invalid '{VERSION_KEY}' key: {version}"))?;
+
+    Ok(version)
+}
+
+pub(crate) fn migrate(
+    mut value: Value,
+    migrations: &[(u32, MigrationFn)]
+) -> Result<Value> {
+    let from = version_of(&value)?;
+    let latest = migrations.last().map_or(0, |(version, _)| *version);
+
+    if from > latest {
+        return Err(NewerVersion { found: from, supported: latest }.into());
+    }
+
+    for (version, migration) in migrations {
+        if *version <= from {
+            continue;
+        }
+
+        value = migration(value)
+            .with_context(|| format!("Migration to version {version} failed"))?;
+    }
+
+    if let Value::Object(map) = &mut value {
+        map.remove(VERSION_KEY);
+    }
+
+    Ok(value)
+}
+
+pub(crate) fn stamp(value: &mut Value, version: u32) -> Result<()> {
+    let Value::Object(map) = value else {
+        bail!("Only map-like states can be versioned");
+    };
+
+    map.insert(VERSION_KEY.into(), version.into());
+
+    Ok(())
+}
+
+/// Removes null fields, which most formats can't represent, returning the paths of those outside arrays
+pub(crate) fn strip_nulls(value: &mut Value) -> Vec<Vec<String>> {
+    let mut paths = Vec::new();
+    strip_nulls_at(value, &mut Vec::new(), &mut paths);
+    paths
+}
+
+fn strip_nulls_at(value: &mut Value, path: &mut Vec<String>, paths: &mut Vec<Vec<String>>) {
+    match value {
+        Value::Object(map) => {
+            map.retain(|key, value| {
+                if value.is_null() {
+                    let mut null = path.clone();
+                    null.push(key.clone());
+                    paths.push(null);
+                }
+                !value.is_null()
+            });
+
+            for (key, value) in map.iter_mut() {
+                path.push(key.clone());
+                strip_nulls_at(value, path, paths);
+                path.pop();
+            }
+        },
+        Value::Array(values) => values.iter_mut().for_each(|value| { strip_nulls(value); }),
+        _ => {},
+    }
+}
+
+pub(crate) fn record_nulls(value: &mut Value, paths: Vec<Vec<String>>) {
+    if paths.is_empty() {
+        return;
+    }
+
+    if let Value::Object(map) = value {
+        map.insert(NULLS_KEY.into(), paths.into());
+    }
+}
+
+pub(crate) fn take_nulls(value: &mut Value) -> Vec<Vec<String>> {
+    let Value::Object(map) = value else { return Vec::new() };
+
+    map.remove(NULLS_KEY)
+        .and_then(|paths| serde_json::from_value(paths).ok())
+        .unwrap_or_default()
+}
+
+/// Sets the recorded fields back to null where they still exist
+pub(crate) fn restore_nulls(value: &mut Value, paths: &[Vec<String>]) {
+    for path in paths {
+        let Some((last, parents)) = path.split_last() else { continue };
+
+        let parent = parents
+            .iter()
+            .try_fold(&mut *value, |value, key| value.get_mut(key));
+
+        if let Some(Value::Object(map)) = parent {
+            if let Some(field) = map.get_mut(last) {
+                *field = Value::Null;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn migrations() -> Vec<(u32, MigrationFn)> {
+        vec![
+            (1, Arc::new(|mut value: Value| {
+                value["volume"] = value["level"].take();
+                value.as_object_mut().unwrap().remove("level");
+                Ok(value)
+            })),
+            (2, Arc::new(|mut value: Value| {
+                value["theme"] = json!("dark");
+                Ok(value)
+            }))
+        ]
+    }
+
+    #[test]
+    fn runs_migrations_after_the_file_version() {
+        assert_eq!(migrate(json!({ "level": 3 }), &migrations()).unwrap(), json!({ "volume": 3, "theme": "dark" }));
+        assert_eq!(migrate(json!({ "__version": 1, "volume": 3 }), &migrations()).unwrap(), json!({ "volume": 3, "theme": "dark" }));
+        assert_eq!(migrate(json!({ "__version": 2, "volume": 3 }), &migrations()).unwrap(), json!({ "volume": 3 }));
+    }
+
+    #[test]
+    fn keeps_fields_named_version() {
+        let mut value = json!({ "version": "1.2.0" });
+        stamp(&mut value, 2).unwrap();
+
+        assert_eq!(version_of(&value).unwrap(), 2);
+        assert_eq!(migrate(value, &migrations()).unwrap(), json!({ "version": "1.2.0" }));
+    }
+
+    #[test]
+    fn refuses_newer_versions() {
+        let error = migrate(json!({ "__version": 3 }), &migrations()).unwrap_err();
+        let newer = error.downcast_ref::<NewerVersion>().unwrap();
+
+        assert_eq!((newer.found, newer.supported), (3, 2));
+    }
+}
@@ -0,0 +1,311 @@
+use std::{sync::Arc, time::Duration, path::PathBuf};
+
+use anyhow::{Result, Error, Context};
+use serde_json::Value;
+use tauri::AppHandle;
+
+use crate::{migrations::MigrationFn, storage::{StorageBackend, FileSystem}, overrides::FieldOverrides};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Durability {
+    /// Write through a temporary file and rename it, without syncing to disk
+    None,
+    /// Additionally sync the temporary file's data before the rename
+    Flush,
+    /// Additionally sync file metadata and the parent directory after the rename
+    #[default]
+    Full
+}
+
+pub type RecoveryFn<T> = Arc<dyn Fn(&Error) -> Result<T> + Send + Sync>;
+
+pub enum RecoveryPolicy<T> {
+    /// Start from the defaults and overwrite the file on the next save
+    UseDefault,
+    /// Abort plugin setup with the load error, leaving an unreadable file in place
+    FailStartup,
+    /// Build the value from the load error, or fail startup by returning an error
+    Custom(RecoveryFn<T>)
+}
+
+impl<T> RecoveryPolicy<T>
+where T: Default
+{
+    pub fn custom(function: impl Fn(&Error) -> Result<T> + Send + Sync + 'static) -> Self {
+        Self::Custom(Arc::new(function))
+    }
+
+    /// `defaults` is what the state resets to, including any bundled defaults layer
+    pub(crate) fn recover(&self, error: Error, defaults: T) -> Result<T> {
+        match self {
+            Self::UseDefault => Ok(defaults),
+            Self::FailStartup => Err(error),
+            Self::Custom(function) => function(&error),
+        }
+    }
+}
+
+impl<T> Clone for RecoveryPolicy<T> {
+    fn clone(&self) -> Self {
+        match self {
+            Self::UseDefault => Self::UseDefault,
+            Self::FailStartup => Self::FailStartup,
+            Self::Custom(function) => Self::Custom(function.clone()),
+        }
+    }
+}
+
+impl<T> Default for RecoveryPolicy<T> {
+    fn default() -> Self {
+        Self::UseDefault
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SavePolicy {
+    /// Write the file inside every `mutate` call
+    #[default]
+    OnEveryMutation,
+    /// Write from a background task once mutations pause for the given window
+    Debounced(Duration),
+    /// Write pending changes from a background task at most once per period
+    Interval(Duration),
+    /// Only write on `save()` and on application exit
+    Manual
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LockPolicy {
+    /// Do not coordinate with other processes
+    #[default]
+    None,
+    /// Hold an exclusive lock for the lifetime of the app, other instances open the state read-only
+    Exclusive,
+    /// Lock only around reads and writes, merging changes made by other instances before writing
+    /// and reloading when they write. Requires the `watch` feature.
+    SharedWithReload
+}
+
+pub type DirResolver = Arc<dyn Fn(&AppHandle) -> Result<PathBuf> + Send + Sync>;
+
+/// Directory that relative state paths are resolved against
+#[derive(Default)]
+pub enum BaseDir {
+    #[default]
+    AppConfig,
+    AppData,
+    AppLocalData,
+    AppCache,
+    Home,
+    /// Directory of the running executable, for portable builds
+    Executable,
+    Path(PathBuf),
+    Custom(DirResolver)
+}
+
+impl BaseDir {
+    pub fn custom(resolver: impl Fn(&AppHandle) -> Result<PathBuf> + Send + Sync + 'static) -> Self {
+        Self::Custom(Arc::new(resolver))
+    }
+
+    pub(crate) fn resolve(&self, handle: &AppHandle) -> Result<PathBuf> {
+        let resolver = handle.path_resolver();
+
+        match self {
+            Self::AppConfig => resolver.app_config_dir().context("Failed to resolve app config directory"),
+            Self::AppData => resolver.app_data_dir().context("Failed to resolve app data directory"),
+            Self::AppLocalData => resolver.app_local_data_dir().context("Failed to resolve app local data directory"),
+            Self::AppCache => resolver.app_cache_dir().context("Failed to resolve app cache directory"),
+            Self::Home => tauri::api::path::home_dir().context("Failed to resolve home directory"),
+            Self::Executable => {
+                let executable = std::env::current_exe().context("Failed to resolve executable path")?;
+                executable
+                    .parent()
+                    .map(PathBuf::from)
+                    .context("Executable path has no parent directory")
+            },
+            Self::Path(path) => Ok(path.clone()),
+            Self::Custom(resolver) => resolver(handle),
+        }
+    }
+}
+
+impl Clone for BaseDir {
+    fn clone(&self) -> Self {
+        match self {
+            Self::AppConfig => Self::AppConfig,
+            Self::AppData => Self::AppData,
+            Self::AppLocalData => Self::AppLocalData,
+            Self::AppCache => Self::AppCache,
+            Self::Home => Self::Home,
+            Self::Executable => Self::Executable,
+            Self::Path(path) => Self::Path(path.clone()),
+            Self::Custom(resolver) => Self::Custom(resolver.clone()),
+        }
+    }
+}
+
+pub struct FileOptions<T> {
+    pub(crate) durability: Durability,
+    pub(crate) recovery: RecoveryPolicy<T>,
+    pub(crate) quarantine_limit: usize,
+    pub(crate) backups: usize,
+    pub(crate) migrations: Vec<(u32, MigrationFn)>,
+    pub(crate) lenient: bool,
+    pub(crate) save_policy: SavePolicy,
+    pub(crate) max_delay: Option<Duration>,
+    pub(crate) lock_policy: LockPolicy,
+    pub(crate) backend: Option<Arc<dyn StorageBackend>>,
+    pub(crate) base_dir: BaseDir,
+    pub(crate) defaults_resource: Option<PathBuf>,
+    pub(crate) defaults: Option<Arc<Value>>,
+    pub(crate) overrides: Option<Arc<FieldOverrides>>,
+    #[cfg(feature = "watch")]
+    pub(crate) watch: bool
+}
+
+impl<T> FileOptions<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn durability(mut self, durability: Durability) -> Self {
+        self.durability = durability;
+        self
+    }
+
+    pub fn recovery(mut self, recovery: RecoveryPolicy<T>) -> Self {
+        self.recovery = recovery;
+        self
+    }
+
+    pub fn quarantine_limit(mut self, limit: usize) -> Self {
+        self.quarantine_limit = limit;
+        self
+    }
+
+    pub fn backups(mut self, count: usize) -> Self {
+        self.backups = count;
+        self
+    }
+
+    /// Registers a migration that upgrades a file to `version`
+    pub fn migrate(
+        mut self,
+        version: u32,
+        migration: impl Fn(Value) -> Result<Value> + Send + Sync + 'static
+    ) -> Self {
+        self.migrations.retain(|(existing, _)| *existing != version);
+        self.migrations.push((version, Arc::new(migration)));
+        self.migrations.sort_by_key(|(version, _)| *version);
+        self
+    }
+
+    /// Fills fields missing from the file with their defaults instead of failing to load
+    pub fn lenient(mut self, lenient: bool) -> Self {
+        self.lenient = lenient;
+        self
+    }
+
+    pub fn save_policy(mut self, save_policy: SavePolicy) -> Self {
+        self.save_policy = save_policy;
+        self
+    }
+
+    pub fn debounce(self, window: Duration) -> Self {
+        self.save_policy(SavePolicy::Debounced(window))
+    }
+
+    /// Upper bound on how long a debounced write can be postponed by continuous mutations
+    pub fn max_delay(mut self, max_delay: Duration) -> Self {
+        self.max_delay = Some(max_delay);
+        self
+    }
+
+    /// Coordinates access to the file between several running instances of the app
+    pub fn lock_policy(mut self, lock_policy: LockPolicy) -> Self {
+        self.lock_policy = lock_policy;
+        self
+    }
+
+    /// Directory the state's path is relative to, the app config directory by default
+    pub fn base_dir(mut self, base_dir: BaseDir) -> Self {
+        self.base_dir = base_dir;
+        self
+    }
+
+    /// Layers the file over a bundled resource, so only values that differ from it are saved
+    pub fn defaults_resource(mut self, resource: impl Into<PathBuf>) -> Self {
+        self.defaults_resource = Some(resource.into());
+        self
+    }
+
+    /// Stores the state somewhere other than the app config directory on disk
+    pub fn backend(mut self, backend: impl StorageBackend) -> Self {
+        self.backend = Some(Arc::new(backend));
+        self
+    }
+
+    /// Reloads the state when its file is changed by another program
+    #[cfg(feature = "watch")]
+    pub fn watch(mut self, watch: bool) -> Self {
+        self.watch = watch;
+        self
+    }
+
+    pub(crate) fn storage(&self) -> Arc<dyn StorageBackend> {
+        self.backend
+            .clone()
+            .unwrap_or_else(|| Arc::new(FileSystem::new(self.durability)))
+    }
+
+    pub(crate) fn schema_version(&self) -> u32 {
+        self.migrations.last().map_or(0, |(version, _)| *version)
+    }
+}
+
+impl<T> Clone for FileOptions<T> {
+    fn clone(&self) -> Self {
+        Self {
+            durability: self.durability,
+            recovery: self.recovery.clone(),
+            quarantine_limit: self.quarantine_limit,
+            backups: self.backups,
+            migrations: self.migrations.clone(),
+            lenient: self.lenient,
+            save_policy: self.save_policy,
+            max_delay: self.max_delay,
+            lock_policy: self.lock_policy,
+            backend: self.backend.clone(),
+            base_dir: self.base_dir.clone(),
+            defaults_resource: self.defaults_resource.clone(),
+            defaults: self.defaults.clone(),
+            overrides: self.overrides.clone(),
+            #[cfg(feature = "watch")]
+            watch: self.watch
+        }
+    }
+}
+
+impl<T> Default for FileOptions<T> {
+    fn default() -> Self {
+        Self {
+            durability: Durability::default(),
+            recovery: RecoveryPolicy::default(),
+            quarantine_limit: 3,
+            backups: 0,
+            migrations: Vec::new(),
+            lenient: false,
+            save_policy: SavePolicy::default(),
+            max_delay: None,
+            lock_policy: LockPolicy::default(),
+            backend: None,
+            base_dir: BaseDir::default(),
+            defaults_resource: None,
+            defaults: None,
+            overrides: None,
+            #[cfg(feature = "watch")]
+            watch: false
+        }
+    }
+}
@@ -0,0 +1,203 @@
+use std::{sync::Mutex, ops::Not};
+
+use serde_json::Value;
+
+/// Where startup overrides for file states are read from, e.g. `MYAPP_SETTINGS__THEME=dark`
+/// or `--set settings.theme=dark`
+#[derive(Clone, Debug)]
+pub struct OverrideSources {
+    prefix: String,
+    separator: String,
+    arg: Option<String>
+}
+
+impl OverrideSources {
+    /// Reads environment variables starting with `prefix`, followed by the state key and field path
+    pub fn new(prefix: impl Into<String>) -> Self {
+        Self {
+            prefix: prefix.into(),
+            separator: String::from("__"),
+            arg: Some(String::from("--set"))
+        }
+    }
+
+    /// Separates the state key and nested field names in environment variables
+    pub fn separator(mut self, separator: impl Into<String>) -> Self {
+        self.separator = separator.into();
+        self
+    }
+
+    /// Command line flag taking `key.field=value`, `--set` by default
+    pub fn arg(mut self, flag: impl Into<String>) -> Self {
+        self.arg = Some(flag.into());
+        self
+    }
+
+    pub fn without_args(mut self) -> Self {
+        self.arg = None;
+        self
+    }
+
+    pub(crate) fn collect(&self) -> CollectedOverrides {
+        let mut entries = Vec::new();
+
+        // Entries that aren't valid UTF-8 can't name a state, so they are skipped rather than panicking
+        let vars = std::env::vars_os()
+            .filter_map(|(name, raw)| Some((name.into_string().ok()?, raw.into_string().ok()?)));
+
+        for (name, raw) in vars {
+            let Some(rest) = name.strip_prefix(&self.prefix) else { continue };
+            if self.separator.is_empty() {
+                continue;
+            }
+
+            let mut path: Vec<String> = rest.split(self.separator.as_str()).map(String::from).collect();
+            if path.len() < 2 {
+                continue;
+            }
+
+            let key = path.remove(0);
+            entries.push((key, path, raw));
+        }
+
+        if let Some(flag) = &self.arg {
+            let prefixed = format!("{flag}=");
+            let mut args = std::env::args_os()
+                .skip(1)
+                .map(|arg| arg.into_string().ok());
+
+            while let Some(arg) = args.next() {
+                let Some(arg) = arg else { continue };
+
+                let assignment = match arg.strip_prefix(&prefixed) {
+                    Some(assignment) => assignment.to_owned(),
+                    None if arg == *flag => match args.next() {
+                        Some(Some(assignment)) => assignment,
+                        Some(None) => continue,
+                        None => break,
+                    },
+                    None => continue,
+                };
+
+                let Some((path, raw)) = assignment.split_once('=') else { continue };
+
+                let mut path: Vec<String> = path.split('.').map(String::from).collect();
+                if path.len() < 2 {
+                    continue;
+                }
+
+                let key = path.remove(0);
+                entries.push((key, path, raw.to_owned()));
+            }
+        }
+
+        CollectedOverrides { entries }
+    }
+}
+
+fn normalize(name: &str) -> String {
+    name.chars()
+        .map(|char| match char.is_ascii_alphanumeric() {
+            true => char.to_ascii_uppercase(),
+            false => '_',
+        })
+        .collect()
+}
+
+/// Overrides found at startup, managed as app state
+pub(crate) struct CollectedOverrides {
+    entries: Vec<(String, Vec<String>, String)>
+}
+
+impl CollectedOverrides {
+    pub fn for_state(&self, key: &str) -> Option<FieldOverrides> {
+        let key = normalize(key);
+
+        let fields: Vec<_> = self.entries
+            .iter()
+            .filter(|(state, _, _)| normalize(state) == key)
+            .map(|(_, path, raw)| (path.clone(), raw.clone()))
+            .collect();
+
+        fields.is_empty().not().then(|| FieldOverrides {
+            fields,
+            underlying: Mutex::new(Vec::new())
+        })
+    }
+}
+
+/// Overrides of one state's fields, along with the values they hide so those can be saved instead
+pub(crate) struct FieldOverrides {
+    fields: Vec<(Vec<String>, String)>,
+    underlying: Mutex<Vec<(Vec<String>, Value)>>
+}
+
+impl FieldOverrides {
+    /// Applies the overrides, remembering the values they replace
+    pub fn apply(&self, value: &mut Value) {
+        let mut underlying = Vec::new();
+
+        for (path, raw) in &self.fields {
+            let Some((path, target)) = find(value, path) else {
+                eprintln!("Ignoring override of unknown field '{}'", path.join("."));
+                continue;
+            };
+
+            let replacement = match target {
+                Value::String(_) => Value::String(raw.clone()),
+                _ => serde_json::from_str(raw).unwrap_or_else(|_| Value::String(raw.clone())),
+            };
+
+            underlying.push((path, std::mem::replace(target, replacement)));
+        }
+
+        if let Ok(mut lock) = self.underlying.lock() {
+            *lock = underlying;
+        }
+    }
+
+    pub fn clear(&self) {
+        if let Ok(mut underlying) = self.underlying.lock() {
+            underlying.clear();
+        }
+    }
+
+    /// Puts back the values hidden by the overrides
+    pub fn restore(&self, value: &mut Value) {
+        let Ok(underlying) = self.underlying.lock() else { return };
+
+        for (path, hidden) in underlying.iter() {
+            if let Some((_, target)) = find(value, path) {
+                *target = hidden.clone();
+            }
+        }
+    }
+
+    /// Dotted paths of the overridden fields
+    pub fn fields(&self) -> Vec<String> {
+        self.underlying
+            .lock()
+            .map(|underlying| underlying.iter().map(|(path, _)| path.join(".")).collect())
+            .unwrap_or_default()
+    }
+}
+
+/// Field at `path`, matching names case-insensitively, along with its exact path
+fn find<'a>(value: &'a mut Value, path: &[String]) -> Option<(Vec<String>, &'a mut Value)> {
+    let mut exact = Vec::new();
+    let mut target = value;
+
+    for name in path {
+        let Value::Object(object) = target else { return None };
+
+        let key = object
+            .keys()
+            .find(|key| normalize(key) == normalize(name))?
+            .clone();
+
+        target = object.get_mut(&key)?;
+        exact.push(key);
+    }
+
+    Some((exact, target))
+}
@@ -0,0 +1,103 @@
+use std::{path::{Path, PathBuf}, fs::{OpenOptions, remove_file}};
+
+use anyhow::{Result, Context};
+
+use crate::{options::{FileOptions, BaseDir, LockPolicy}, storage::read_only::ReadOnlyFiles};
+
+const PROBE_FILE: &str = ".synced-state-probe";
+
+/// Keeps file states next to the executable when a marker file or environment variable is present
+#[derive(Clone, Debug)]
+pub struct PortableMode {
+    marker: String,
+    env_var: Option<String>
+}
+
+impl Default for PortableMode {
+    fn default() -> Self {
+        Self {
+            marker: String::from("portable.txt"),
+            env_var: None
+        }
+    }
+}
+
+impl PortableMode {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Name of the file beside the executable that enables portable mode
+    pub fn marker(mut self, marker: impl Into<String>) -> Self {
+        self.marker = marker.into();
+        self
+    }
+
+    /// Environment variable that enables portable mode when set
+    pub fn env_var(mut self, env_var: impl Into<String>) -> Self {
+        self.env_var = Some(env_var.into());
+        self
+    }
+
+    pub(crate) fn detect(&self) -> Result<Option<Portable>> {
+        let executable = std::env::current_exe().context("Failed to resolve executable path")?;
+        let dir = executable
+            .parent()
+            .map(PathBuf::from)
+            .context("Executable path has no parent directory")?;
+
+        let enabled_by_env = self.env_var
+            .as_ref()
+            .is_some_and(|env_var| std::env::var_os(env_var).is_some());
+
+        if dir.join(&self.marker).exists() || enabled_by_env {
+            let read_only = is_writable(&dir).is_err();
+            return Ok(Some(Portable { dir, read_only }));
+        }
+
+        Ok(None)
+    }
+}
+
+fn is_writable(dir: &Path) -> Result<()> {
+    let probe = dir.join(PROBE_FILE);
+
+    OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(&probe)?;
+
+    remove_file(&probe)?;
+
+    Ok(())
+}
+
+/// Portable directory detected at startup, managed as app state
+pub(crate) struct Portable {
+    pub dir: PathBuf,
+    pub read_only: bool
+}
+
+impl Portable {
+    /// Why changes are not being saved, when the portable directory is read-only
+    pub fn disabled_reason(&self) -> Option<String> {
+        self.read_only.then(|| format!("Portable directory '{}' is read-only", self.dir.display()))
+    }
+
+    /// Points a file-system state at the portable directory, keeping writes in memory if it is read-only
+    pub fn apply<T>(&self, options: FileOptions<T>) -> FileOptions<T> {
+        if options.backend.is_some() {
+            return options;
+        }
+
+        let options = options.base_dir(BaseDir::Path(self.dir.clone()));
+
+        match self.read_only {
+            true => options
+                .backend(ReadOnlyFiles::default())
+                .lock_policy(LockPolicy::None),
+            false => options,
+        }
+    }
+}
@@ -0,0 +1,309 @@
+use std::{collections::HashMap, sync::{Arc, RwLock}, any::{Any, TypeId}, ops::Not};
+
+use serde::{Serialize, Deserialize};
+use serde_json::Value;
+use anyhow::{Result, anyhow, bail};
+use tokio::sync::broadcast;
+
+use crate::{synced_state::{Synced, Versioned}, saveable_state::LoadReport, synced_state_file::SyncedFile, formats::Format, storage::BoxFuture, utils::merge::merge};
+
+/// What the frontend may do with a state through the plugin's commands
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Access {
+    #[default]
+    None,
+    Read,
+    Write,
+    ReadWrite
+}
+
+impl Access {
+    fn can_read(self) -> bool {
+        matches!(self, Self::Read | Self::ReadWrite)
+    }
+
+    fn can_write(self) -> bool {
+        matches!(self, Self::Write | Self::ReadWrite)
+    }
+}
+
+/// A state accessed through `serde_json` values
+pub(crate) trait ErasedState: Send + Sync {
+    fn get(&self) -> BoxFuture<'_, Result<Versioned<Value>>>;
+    fn set(&self, value: Value) -> BoxFuture<'_, Result<()>>;
+    /// Deep merges `partial` into the value under the state's lock
+    fn patch(&self, partial: Value) -> BoxFuture<'_, Result<()>>;
+    fn reset(&self) -> BoxFuture<'_, ()>;
+    fn save(&self) -> BoxFuture<'_, Result<()>>;
+    /// How a file state was loaded, `None` for in-memory states
+    fn load_report(&self) -> BoxFuture<'_, Option<LoadReport>>;
+    fn is_read_only(&self) -> BoxFuture<'_, bool>;
+}
+
+fn patched<T>(state: &T, partial: Value) -> Result<T>
+where T: Serialize + for<'a> Deserialize<'a>
+{
+    let mut value = serde_json::to_value(state)?;
+    merge(&mut value, partial);
+    Ok(serde_json::from_value(value)?)
+}
+
+pub(crate) struct PlainState<T, Tag>(pub Synced<T, Tag>);
+
+impl<T, Tag> ErasedState for PlainState<T, Tag>
+where T: Default + Serialize + for<'a> Deserialize<'a> + Clone + Send + Sync + 'static, Tag: 'static
+{
+    fn get(&self) -> BoxFuture<'_, Result<Versioned<Value>>> {
+        Box::pin(async move {
+            let Versioned { key, version, value } = self.0.get_with_version().await;
+            Ok(Versioned { key, version, value: serde_json::to_value(value)? })
+        })
+    }
+
+    fn set(&self, value: Value) -> BoxFuture<'_, Result<()>> {
+        Box::pin(async move {
+            self.0.set(serde_json::from_value(value)?).await;
+            Ok(())
+        })
+    }
+
+    fn patch(&self, partial: Value) -> BoxFuture<'_, Result<()>> {
+        Box::pin(self.0.try_update(|state| patched(state, partial)))
+    }
+
+    fn reset(&self) -> BoxFuture<'_, ()> {
+        Box::pin(self.0.reset())
+    }
+
+    fn save(&self) -> BoxFuture<'_, Result<()>> {
+        Box::pin(async { Ok(()) })
+    }
+
+    fn load_report(&self) -> BoxFuture<'_, Option<LoadReport>> {
+        Box::pin(async { None })
+    }
+
+    fn is_read_only(&self) -> BoxFuture<'_, bool> {
+        Box::pin(async { false })
+    }
+}
+
+pub(crate) struct FileState<T, F, Tag>(pub SyncedFile<T, F, Tag>)
+where T: Default + Serialize + for<'a> Deserialize<'a>, F: Format;
+
+impl<T, F, Tag> ErasedState for FileState<T, F, Tag>
+where T: Default + Serialize + for<'a> Deserialize<'a> + Clone + Send + Sync + 'static, F: Format, Tag: 'static
+{
+    fn get(&self) -> BoxFuture<'_, Result<Versioned<Value>>> {
+        Box::pin(async move {
+            let Versioned { key, version, value } = self.0.get_with_version().await;
+            Ok(Versioned { key, version, value: serde_json::to_value(value)? })
+        })
+    }
+
+    fn set(&self, value: Value) -> BoxFuture<'_, Result<()>> {
+        Box::pin(async move {
+            self.0.set(serde_json::from_value(value)?).await;
+            Ok(())
+        })
+    }
+
+    fn patch(&self, partial: Value) -> BoxFuture<'_, Result<()>> {
+        Box::pin(self.0.try_update(|state| patched(state, partial)))
+    }
+
+    fn reset(&self) -> BoxFuture<'_, ()> {
+        Box::pin(self.0.reset())
+    }
+
+    fn save(&self) -> BoxFuture<'_, Result<()>> {
+        Box::pin(self.0.save())
+    }
+
+    fn load_report(&self) -> BoxFuture<'_, Option<LoadReport>> {
+        Box::pin(async move { Some(self.0.load_report().await) })
+    }
+
+    fn is_read_only(&self) -> BoxFuture<'_, bool> {
+        Box::pin(self.0.is_read_only())
+    }
+}
+
+struct Entry {
+    type_id: TypeId,
+    type_name: &'static str,
+    typed: Arc<dyn Any + Send + Sync>,
+    erased: Arc<dyn ErasedState>,
+    updates: broadcast::Sender<Value>
+}
+
+/// Every managed state indexed by its key, managed as app state.
+/// Tauri only tells states apart by type, so this is the way to reach one by key.
+pub struct StateRegistry {
+    access: HashMap<String, Access>,
+    entries: RwLock<HashMap<String, Entry>>
+}
+
+impl StateRegistry {
+    pub(crate) fn new(access: HashMap<String, Access>) -> Self {
+        Self {
+            access,
+            entries: RwLock::default()
+        }
+    }
+
+    /// Fails when the key or the state's type is already registered, since Tauri would drop the second state
+    pub(crate) fn register<T, Tag>(&self, state: &Synced<T, Tag>, erased: Arc<dyn ErasedState>) -> Result<()>
+    where T: Send + Sync + 'static, Tag: 'static
+    {
+        let mut entries = self.entries
+            .write()
+            .map_err(|_| anyhow!("State registry is poisoned"))?;
+
+        let key = &state.key;
+        let type_id = TypeId::of::<Synced<T, Tag>>();
+        let type_name = std::any::type_name::<T>();
+
+        if entries.contains_key(key) {
+            bail!("State key '{key}' is managed more than once");
+        }
+
+        if let Some((existing, _)) = entries.iter().find(|(_, entry)| entry.type_id == type_id) {
+            bail!("States '{existing}' and '{key}' both have type '{type_name}', give one of them a tag to manage both");
+        }
+
+        entries.insert(key.clone(), Entry {
+            type_id,
+            type_name,
+            typed: Arc::new(state.clone()),
+            erased,
+            updates: broadcast::channel(16).0
+        });
+
+        Ok(())
+    }
+
+    /// Sends an updated value to the state's subscribers
+    pub(crate) fn notify(&self, key: &str, value: &impl Serialize) {
+        let Ok(entries) = self.entries.read() else { return };
+        let Some(entry) = entries.get(key) else { return };
+
+        if entry.updates.receiver_count() == 0 {
+            return;
+        }
+
+        match serde_json::to_value(value) {
+            Ok(value) => { entry.updates.send(value).ok(); },
+            Err(error) => eprintln!("Failed to notify subscribers of '{key}' state: {error}"),
+        }
+    }
+
+    /// The state managed under `key`, when it has type `T`.
+    /// File states have type `Saveable<T, F>`.
+    pub fn get<T>(&self, key: &str) -> Option<Synced<T>>
+    where T: Send + Sync + 'static
+    {
+        self.get_tagged::<T, ()>(key)
+    }
+
+    /// The state managed under `key`, when it has type `T` and tag `Tag`
+    pub fn get_tagged<T, Tag>(&self, key: &str) -> Option<Synced<T, Tag>>
+    where T: Send + Sync + 'static, Tag: 'static
+    {
+        self.entries
+            .read()
+            .ok()?
+            .get(key)?
+            .typed
+            .downcast_ref::<Synced<T, Tag>>()
+            .cloned()
+    }
+
+    pub fn keys(&self) -> Vec<String> {
+        self.entries
+            .read()
+            .map(|entries| entries.keys().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// Name of the type the state under `key` was managed with
+    pub fn type_name(&self, key: &str) -> Option<&'static str> {
+        Some(self.entries.read().ok()?.get(key)?.type_name)
+    }
+
+    fn erased(&self, key: &str) -> Result<Arc<dyn ErasedState>> {
+        self.entries
+            .read()
+            .map_err(|_| anyhow!("State registry is poisoned"))?
+            .get(key)
+            .map(|entry| entry.erased.clone())
+            .ok_or_else(|| anyhow!("State '{key}' is not managed"))
+    }
+
+    pub async fn value(&self, key: &str) -> Result<Value> {
+        Ok(self.erased(key)?.get().await?.value)
+    }
+
+    /// The value along with the version of the last update emitted for it
+    pub async fn versioned(&self, key: &str) -> Result<Versioned<Value>> {
+        self.erased(key)?.get().await
+    }
+
+    pub async fn set_value(&self, key: &str, value: Value) -> Result<()> {
+        self.erased(key)?.set(value).await
+    }
+
+    /// Deep merges `partial` into the current value
+    pub async fn patch(&self, key: &str, partial: Value) -> Result<()> {
+        self.erased(key)?.patch(partial).await
+    }
+
+    pub async fn reset(&self, key: &str) -> Result<()> {
+        self.erased(key)?.reset().await;
+        Ok(())
+    }
+
+    /// Writes a file state now, does nothing for in-memory states
+    pub async fn save(&self, key: &str) -> Result<()> {
+        self.erased(key)?.save().await
+    }
+
+    pub async fn load_report(&self, key: &str) -> Result<Option<LoadReport>> {
+        Ok(self.erased(key)?.load_report().await)
+    }
+
+    /// Whether another instance holds the state's file, so changes are not saved
+    pub async fn is_read_only(&self, key: &str) -> Result<bool> {
+        Ok(self.erased(key)?.is_read_only().await)
+    }
+
+    /// Receives every value the state takes from now on
+    pub fn subscribe(&self, key: &str) -> Result<broadcast::Receiver<Value>> {
+        self.entries
+            .read()
+            .map_err(|_| anyhow!("State registry is poisoned"))?
+            .get(key)
+            .map(|entry| entry.updates.subscribe())
+            .ok_or_else(|| anyhow!("State '{key}' is not managed"))
+    }
+
+    pub(crate) fn check_read(&self, key: &str) -> Result<()> {
+        let access = self.access.get(key).copied().unwrap_or_default();
+
+        if access.can_read().not() {
+            bail!("State '{key}' is not readable from the frontend");
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn check_write(&self, key: &str) -> Result<()> {
+        let access = self.access.get(key).copied().unwrap_or_default();
+
+        if access.can_write().not() {
+            bail!("State '{key}' is not writable from the frontend");
+        }
+
+        Ok(())
+    }
+}
@@ -1,22 +1,84 @@
-use std::{path::{PathBuf, Path}, ops::Not};
+use std::{path::{PathBuf, Path}, ops::Not, marker::PhantomData, sync::Arc, fmt};
 
 use serde::{Serialize, Deserialize};
+use serde_json::{Value, Map};
 
-use tokio::fs::{write, read};
+use anyhow::{Result, Context, bail};
 
-use anyhow::Result;
+use crate::{
+    utils::{
+        quarantine::quarantine,
+        backups::{rotate_backups, backup_path, pre_migration_path},
+        merge::{merge, missing_keys, three_way, changed}
+    },
+    formats::{Format, Toml, header},
+    options::{FileOptions, LockPolicy, RecoveryPolicy},
+    migrations,
+    writer::Writer,
+    dirty::DirtyFlag,
+    fingerprint::Fingerprint,
+    file_lock::StateLock
+};
 
-use crate::utils::create_dir_all_without_file_name::create_dir_all_without_file_name;
+pub type SaveableToml<T> = Saveable<T, Toml>;
 
-pub struct SaveableToml<T>
-where T: Default + Serialize + for<'a> Deserialize<'a>
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct LoadReport {
+    /// Why the file could not be loaded, when the state was recovered instead
+    pub load_error: Option<String>,
+    pub migrated_from: Option<u32>,
+    pub filled: Vec<String>,
+    pub dropped: Vec<String>
+}
+
+impl LoadReport {
+    fn log(&self, path: &Path) {
+        let path = path.display();
+
+        if let Some(version) = self.migrated_from {
+            eprintln!("Migrated '{path}' from version {version}");
+        }
+        if !self.filled.is_empty() {
+            eprintln!("Filled missing keys in '{path}' with defaults: {}", self.filled.join(", "));
+        }
+        if !self.dropped.is_empty() {
+            eprintln!("Dropped unknown keys from '{path}': {}", self.dropped.join(", "));
+        }
+    }
+}
+
+/// Context of a parse failure, telling whether the file was already moved out of the way
+#[derive(Debug)]
+pub(crate) struct Unreadable {
+    pub path: PathBuf,
+    pub quarantined: Option<PathBuf>
+}
+
+impl fmt::Display for Unreadable {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        match &self.quarantined {
+            Some(quarantined) => write!(formatter, "Failed to parse '{}', moved it to '{}'", self.path.display(), quarantined.display()),
+            None => write!(formatter, "Failed to parse '{}'", self.path.display()),
+        }
+    }
+}
+
+pub struct Saveable<T, F>
+where T: Default + Serialize + for<'a> Deserialize<'a>, F: Format
 {
     pub path: PathBuf,
-    pub state: T
+    pub state: T,
+    pub(crate) options: FileOptions<T>,
+    pub(crate) report: LoadReport,
+    pub(crate) writer: Option<Writer<T>>,
+    pub(crate) dirty: DirtyFlag,
+    pub(crate) fingerprint: Fingerprint,
+    pub(crate) file_lock: Arc<StateLock>,
+    format: PhantomData<F>
 }
 
-impl<T> SaveableToml<T>
-where T: Default + Serialize + for<'a> Deserialize<'a>
+impl<T, F> Saveable<T, F>
+where T: Default + Serialize + for<'a> Deserialize<'a>, F: Format
 {
     pub fn new(
         path: impl AsRef<Path>,
@@ -24,55 +86,551 @@ where T: Default + Serialize + for<'a> Deserialize<'a>
         Self {
             path: PathBuf::from(path.as_ref()),
             state: T::default(),
+            options: FileOptions::default(),
+            report: LoadReport::default(),
+            writer: None,
+            dirty: DirtyFlag::default(),
+            fingerprint: Fingerprint::default(),
+            file_lock: Arc::default(),
+            format: PhantomData
         }
     }
 
-    pub async fn save(&self) -> Result<()> {
-        let path = &self.path;
-        
-        let string = toml::ser::to_string(&self.state)?;
+    pub fn with_options(mut self, options: FileOptions<T>) -> Self {
+        self.options = options;
+        self
+    }
 
-        create_dir_all_without_file_name(path).await?;
+    pub fn load_report(&self) -> &LoadReport {
+        &self.report
+    }
+
+    /// Value the state resets to, including any bundled defaults layer
+    pub fn defaults(&self) -> Result<T> {
+        Self::defaults_of(&self.options)
+    }
+
+    pub(crate) fn defaults_of(options: &FileOptions<T>) -> Result<T> {
+        match &options.defaults {
+            Some(defaults) => Ok(serde_json::from_value(defaults.as_ref().clone())?),
+            None => Ok(T::default()),
+        }
+    }
 
-        write(path, string).await?;
+    /// Fails for options the format can't support, which no recovery policy can make work
+    pub(crate) fn check_options(options: &FileOptions<T>) -> Result<()> {
+        let layered = options.defaults.is_some() || options.defaults_resource.is_some();
+
+        if (options.schema_version() > 0 || options.lenient || layered) && F::SELF_DESCRIBING.not() {
+            bail!("The '{}' format does not support schema migrations, lenient loading or defaults layers", F::EXTENSION);
+        }
+
+        // Without watching the file, other instances' writes would only be noticed on the next write
+        #[cfg(not(feature = "watch"))]
+        if options.lock_policy == LockPolicy::SharedWithReload {
+            bail!("LockPolicy::SharedWithReload requires the 'watch' feature");
+        }
 
         Ok(())
     }
 
-    async fn create_default(
-        path: impl AsRef<Path>,
-    ) -> Result<()> {
+    fn encode(state: &T, options: &FileOptions<T>) -> Result<Vec<u8>> {
+        let version = options.schema_version();
+
+        let layered = options.defaults.is_some();
+
+        let payload = if version == 0 && options.lenient.not() && layered.not() {
+            match &options.overrides {
+                // Encoded as `T` again, since that is how the file is read back
+                Some(overrides) => {
+                    let mut value = serde_json::to_value(state)?;
+                    overrides.restore(&mut value);
+                    F::encode(&serde_json::from_value::<T>(value)?)?
+                },
+                None => F::encode(state)?,
+            }
+        } else {
+            let mut value = serde_json::to_value(state)?;
+
+            if let Some(overrides) = &options.overrides {
+                overrides.restore(&mut value);
+            }
+
+            if let Some(defaults) = &options.defaults {
+                value = changed(defaults, value).unwrap_or_else(|| Value::Object(Map::new()));
+            }
+
+            let nulls = migrations::strip_nulls(&mut value);
+
+            if options.lenient || layered {
+                migrations::record_nulls(&mut value, nulls);
+            }
 
-        Self::new(path).save().await?;
+            if version > 0 {
+                migrations::stamp(&mut value, version)?;
+            }
 
+            F::encode(&value)?
+        };
+
+        Ok(header::wrap::<F>(payload))
+    }
+
+    /// Writes the state, first merging in changes another instance made to the file.
+    /// Returns the merged state when it differs from the one passed in.
+    pub(crate) async fn write(
+        path: &PathBuf,
+        options: &FileOptions<T>,
+        state: &T,
+        file_lock: &StateLock,
+        fingerprint: &Fingerprint
+    ) -> Result<Option<T>> {
+        let _guard = file_lock.write(path).await?;
+
+        let merged = Self::merge_external(path, options, state, file_lock, fingerprint).await?;
+        let state = merged.as_ref().unwrap_or(state);
+
+        let bytes = Self::encode(state, options)?;
+        let backend = options.storage();
+
+        // Rotating for bytes that are already stored would only push out older backups
+        if fingerprint.matches(&bytes).not() {
+            rotate_backups(backend.as_ref(), path, options.backups).await?;
+        }
+
+        // Set before storing, so a watcher woken by a slow write recognises it as ours
+        let previous = fingerprint.get();
+        fingerprint.set(Fingerprint::of(&bytes));
+
+        if let Err(error) = backend.store(path, &bytes).await {
+            fingerprint.set(previous);
+            return Err(error);
+        }
+
+        if file_lock.policy() == LockPolicy::SharedWithReload {
+            file_lock.set_base(serde_json::to_value(state)?);
+        }
+
+        Ok(merged)
+    }
+
+    async fn merge_external(
+        path: &Path,
+        options: &FileOptions<T>,
+        state: &T,
+        file_lock: &StateLock,
+        fingerprint: &Fingerprint
+    ) -> Result<Option<T>> {
+        let backend = options.storage();
+
+        if file_lock.policy() != LockPolicy::SharedWithReload || backend.exists(path).await?.not() {
+            return Ok(None);
+        }
+
+        let bytes = backend.load(path).await?;
+
+        if fingerprint.matches(&bytes) {
+            return Ok(None);
+        }
+
+        let Some(base) = file_lock.base() else { return Ok(None) };
+
+        let theirs = match Self::decode(&bytes, options) {
+            Ok((theirs, _)) => theirs,
+            Err(error) => {
+                eprintln!("Overwriting unreadable external change to '{}': {error:#}", path.display());
+                return Ok(None);
+            },
+        };
+
+        let merged = three_way(&base, serde_json::to_value(state)?, serde_json::to_value(&theirs)?);
+
+        Ok(Some(serde_json::from_value(merged)?))
+    }
+
+    /// Saves the state, returning `true` when changes from another instance were merged into it
+    pub(crate) async fn persist(&mut self) -> Result<bool> {
+        let merged = Self::write(&self.path, &self.options, &self.state, &self.file_lock, &self.fingerprint).await?;
+
+        match merged {
+            Some(merged) => {
+                self.state = merged;
+                Ok(true)
+            },
+            None => Ok(false),
+        }
+    }
+
+    /// Like `persist`, but returns the new fingerprint instead of recording it, for writes that may be rolled back
+    pub(crate) async fn persist_staged(&mut self) -> Result<(bool, u64)> {
+        let staged = Fingerprint::default();
+        staged.set(self.fingerprint.get());
+
+        let merged = Self::write(&self.path, &self.options, &self.state, &self.file_lock, &staged).await?;
+
+        match merged {
+            Some(merged) => {
+                self.state = merged;
+                Ok((true, staged.get()))
+            },
+            None => Ok((false, staged.get())),
+        }
+    }
+
+    pub async fn save(&mut self) -> Result<()> {
+        self.persist().await?;
         Ok(())
     }
 
+    pub(crate) fn decode(bytes: &[u8], options: &FileOptions<T>) -> Result<(T, LoadReport)> {
+        let (state, report) = Self::decode_file(bytes, options)?;
+
+        let Some(overrides) = &options.overrides else { return Ok((state, report)) };
+
+        let mut value = serde_json::to_value(&state)?;
+        overrides.apply(&mut value);
+
+        match serde_json::from_value::<T>(value) {
+            Ok(overridden) => Ok((overridden, report)),
+            Err(error) => {
+                eprintln!("Ignoring overrides that do not fit the state: {error}");
+                overrides.clear();
+                Ok((state, report))
+            },
+        }
+    }
+
+    fn decode_file(bytes: &[u8], options: &FileOptions<T>) -> Result<(T, LoadReport)> {
+        let payload = header::unwrap::<F>(bytes)?;
+
+        let latest = options.schema_version();
+        let mut report = LoadReport::default();
+
+        let layered = options.defaults.is_some();
+
+        if latest == 0 && options.lenient.not() && layered.not() {
+            return Ok((F::decode::<T>(payload)?, report));
+        }
+
+        let mut value = F::decode::<Value>(payload)?;
+        let nulls = migrations::take_nulls(&mut value);
+
+        // Also takes out the version key, so it isn't reported as dropped
+        let from = migrations::version_of(&value)?;
+        value = migrations::migrate(value, &options.migrations)?;
+        report.migrated_from = (from < latest).then_some(from);
+
+        if options.lenient.not() && layered.not() {
+            return Ok((serde_json::from_value::<T>(value)?, report));
+        }
+
+        let mut merged = match &options.defaults {
+            Some(defaults) => defaults.as_ref().clone(),
+            None => serde_json::to_value(T::default())?,
+        };
+
+        if options.lenient && layered.not() {
+            // Saved nulls are restored below rather than filled
+            report.filled = missing_keys(&merged, &value)
+                .into_iter()
+                .filter(|path| nulls.iter().all(|null| &null.join(".") != path))
+                .collect();
+        }
+
+        merge(&mut merged, value.clone());
+        migrations::restore_nulls(&mut merged, &nulls);
+
+        let state = serde_json::from_value::<T>(merged)?;
+
+        if options.lenient {
+            report.dropped = missing_keys(&value, &serde_json::to_value(&state)?);
+        }
+
+        Ok((state, report))
+    }
+
+    /// Defaults of `T` with a bundled defaults file layered over them
+    pub(crate) fn defaults_layer(bytes: &[u8]) -> Result<Value> {
+        let mut defaults = serde_json::to_value(T::default())?;
+        merge(&mut defaults, F::decode::<Value>(header::unwrap::<F>(bytes)?)?);
+        Ok(defaults)
+    }
+
+    pub(crate) async fn read_value(
+        path: impl AsRef<Path>,
+        options: &FileOptions<T>
+    ) -> Result<T> {
+        let bytes = options.storage().load(path.as_ref()).await?;
+        let (value, _) = Self::decode(&bytes, options)?;
+        Ok(value)
+    }
+
+    pub async fn backup_paths(&self) -> Vec<PathBuf> {
+        let backend = self.options.storage();
+        let mut paths = Vec::new();
+
+        for index in 1..=self.options.backups {
+            let path = backup_path(&self.path, index);
+
+            if backend.exists(&path).await.unwrap_or(false) {
+                paths.push(path);
+            }
+        }
+
+        paths
+    }
+
+    async fn restore_from_backups(
+        path: &Path,
+        options: &FileOptions<T>
+    ) -> Option<T> {
+        let path = PathBuf::from(path);
+        let backend = options.storage();
+
+        for index in 1..=options.backups {
+            let backup = backup_path(&path, index);
+
+            if backend.exists(&backup).await.unwrap_or(false).not() {
+                continue;
+            }
+
+            match Self::read_value(&backup, options).await {
+                Ok(value) => {
+                    eprintln!("Restored '{}' from backup '{}'", path.display(), backup.display());
+                    return Some(value);
+                },
+                Err(error) => {
+                    eprintln!("Skipping unreadable backup '{}': {error}", backup.display());
+                },
+            }
+        }
+
+        None
+    }
+
     pub async fn load_path(
         path: impl AsRef<Path>,
+        options: FileOptions<T>
     ) -> Result<Self> {
 
         let path = path.as_ref();
+        let file_lock = StateLock::acquire(path, &options)?;
+
+        Self::load_locked(path, options, Arc::new(file_lock)).await
+    }
+
+    pub(crate) async fn load_locked(
+        path: &Path,
+        options: FileOptions<T>,
+        file_lock: Arc<StateLock>
+    ) -> Result<Self> {
 
-        if path.exists().not() {
-            Self::create_default(path).await?;
+        Self::check_options(&options)?;
+
+        let fingerprint = Fingerprint::default();
+        let backend = options.storage();
+
+        // With a defaults layer this writes an empty user layer, so later changes to the bundled defaults apply
+        let defaults = Self::defaults_of(&options)?;
+
+        if backend.exists(path).await?.not() && file_lock.is_read_only().not() {
+            Self::write(&PathBuf::from(path), &options, &defaults, &file_lock, &fingerprint).await?;
         }
 
-        let bytes = read(&path).await?;
+        let bytes = {
+            let _guard = file_lock.read(path).await?;
+
+            if backend.exists(path).await? {
+                backend.load(path).await?
+            } else {
+                Self::encode(&defaults, &options)?
+            }
+        };
+
+        let (value, report) = match Self::decode(&bytes, &options) {
+            Ok(decoded) => decoded,
+            Err(error) if error.downcast_ref::<migrations::NewerVersion>().is_some() => return Err(error),
+            Err(error) => {
+                let restored = Self::restore_from_backups(path, &options).await;
 
-        let value = match toml::de::from_slice::<T>(&bytes) {
-            Ok(session) => session,
-            Err(_) => {
-                Self::create_default(path).await?;
-                toml::de::from_slice::<T>(&bytes)?
+                // A policy that may fail startup must find the file again on the next launch,
+                // so it is only moved once there is a value to replace it with
+                if restored.is_none() && matches!(options.recovery, RecoveryPolicy::UseDefault).not() {
+                    return Err(error).context(Unreadable { path: PathBuf::from(path), quarantined: None });
+                }
+
+                let quarantined = quarantine(backend.as_ref(), PathBuf::from(path), options.quarantine_limit).await?;
+
+                match restored {
+                    Some(value) => (value, LoadReport::default()),
+                    None => {
+                        return Err(error).context(Unreadable { path: PathBuf::from(path), quarantined: Some(quarantined) });
+                    },
+                }
             },
         };
 
-        let state = Self {
+        report.log(path);
+
+        fingerprint.set(Fingerprint::of(&bytes));
+
+        if file_lock.policy() == LockPolicy::SharedWithReload {
+            file_lock.set_base(serde_json::to_value(&value)?);
+        }
+
+        let mut state = Self {
             path: PathBuf::from(path),
             state: value,
+            options,
+            report,
+            writer: None,
+            dirty: DirtyFlag::default(),
+            fingerprint,
+            file_lock,
+            format: PhantomData
         };
 
+        if let Some(version) = state.report.migrated_from {
+            backend.copy(path, &pre_migration_path(&state.path, version)).await?;
+            state.save().await?;
+        }
+
         Ok(state)
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use crate::storage::Memory;
+
+    use super::*;
+
+    #[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+    struct Settings {
+        volume: u8,
+        theme: String
+    }
+
+    #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+    struct Window {
+        title: Option<String>
+    }
+
+    impl Default for Window {
+        fn default() -> Self {
+            Self { title: Some(String::from("Untitled")) }
+        }
+    }
+
+    #[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+    struct Release {
+        version: String
+    }
+
+    fn layered(memory: &Memory, defaults: Value) -> FileOptions<Settings> {
+        let mut options = FileOptions::new().backend(memory.clone());
+        options.defaults = Some(Arc::new(defaults));
+        options
+    }
+
+    #[test]
+    fn fresh_layered_file_follows_bundled_defaults() {
+        tokio::runtime::Runtime::new().unwrap().block_on(async {
+            let memory = Memory::new();
+
+            let options = layered(&memory, json!({ "volume": 5, "theme": "light" }));
+            let loaded = SaveableToml::<Settings>::load_path("settings", options).await.unwrap();
+            assert_eq!(loaded.state, Settings { volume: 5, theme: String::from("light") });
+
+            let options = layered(&memory, json!({ "volume": 7, "theme": "dark" }));
+            let loaded = SaveableToml::<Settings>::load_path("settings", options).await.unwrap();
+            assert_eq!(loaded.state, Settings { volume: 7, theme: String::from("dark") });
+        });
+    }
+
+    #[test]
+    fn lenient_load_keeps_saved_nulls() {
+        tokio::runtime::Runtime::new().unwrap().block_on(async {
+            let memory = Memory::new();
+            let options = FileOptions::new().backend(memory.clone()).lenient(true);
+
+            let mut loaded = SaveableToml::<Window>::load_path("window", options.clone()).await.unwrap();
+            loaded.state.title = None;
+            loaded.save().await.unwrap();
+
+            let loaded = SaveableToml::<Window>::load_path("window", options).await.unwrap();
+            assert_eq!(loaded.state.title, None);
+            assert!(loaded.report.dropped.is_empty());
+            assert!(loaded.report.filled.is_empty());
+        });
+    }
+
+    #[test]
+    fn layered_load_keeps_saved_nulls() {
+        tokio::runtime::Runtime::new().unwrap().block_on(async {
+            let memory = Memory::new();
+            let mut options = FileOptions::<Window>::new().backend(memory.clone());
+            options.defaults = Some(Arc::new(json!({ "title": "Bundled" })));
+
+            let mut loaded = SaveableToml::<Window>::load_path("window", options.clone()).await.unwrap();
+            assert_eq!(loaded.state.title.as_deref(), Some("Bundled"));
+
+            loaded.state.title = None;
+            loaded.save().await.unwrap();
+
+            let loaded = SaveableToml::<Window>::load_path("window", options).await.unwrap();
+            assert_eq!(loaded.state.title, None);
+        });
+    }
+
+    #[test]
+    fn lenient_load_keeps_fields_named_version() {
+        tokio::runtime::Runtime::new().unwrap().block_on(async {
+            let memory = Memory::new();
+            let options = FileOptions::new().backend(memory.clone()).lenient(true);
+
+            let mut loaded = SaveableToml::<Release>::load_path("release", options.clone()).await.unwrap();
+            loaded.state.version = String::from("1.2.0");
+            loaded.save().await.unwrap();
+
+            let loaded = SaveableToml::<Release>::load_path("release", options).await.unwrap();
+            assert_eq!(loaded.state.version, "1.2.0");
+        });
+    }
+
+    #[test]
+    fn unchanged_saves_keep_backups() {
+        tokio::runtime::Runtime::new().unwrap().block_on(async {
+            let memory = Memory::new();
+            let options = FileOptions::new().backend(memory.clone()).backups(2);
+
+            let mut loaded = SaveableToml::<Settings>::load_path("settings", options).await.unwrap();
+            loaded.state.volume = 3;
+            loaded.save().await.unwrap();
+            loaded.save().await.unwrap();
+            loaded.save().await.unwrap();
+
+            let path = PathBuf::from("settings");
+            assert_eq!(memory.get(backup_path(&path, 1)).unwrap(), SaveableToml::<Settings>::encode(&Settings::default(), &loaded.options).unwrap());
+            assert!(memory.get(backup_path(&path, 2)).is_none());
+        });
+    }
+
+    #[test]
+    fn failing_startup_leaves_unreadable_files_in_place() {
+        tokio::runtime::Runtime::new().unwrap().block_on(async {
+            let memory = Memory::new();
+            memory.insert("settings", "volume = ");
+
+            let options = FileOptions::new().backend(memory.clone()).recovery(RecoveryPolicy::FailStartup);
+            assert!(SaveableToml::<Settings>::load_path("settings", options).await.is_err());
+            assert_eq!(memory.get("settings").unwrap(), b"volume = ");
+
+            let options = FileOptions::new().backend(memory.clone());
+            assert!(SaveableToml::<Settings>::load_path("settings", options).await.is_err());
+            assert!(memory.get("settings").is_none());
+        });
+    }
+}
@@ -0,0 +1,84 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use tokio::fs::{read, read_dir, remove_file, rename, copy};
+
+use crate::{
+    options::Durability,
+    utils::{
+        create_dir_all_without_file_name::create_dir_all_without_file_name,
+        write_atomic::write_atomic
+    }
+};
+
+use super::{StorageBackend, BoxFuture};
+
+/// Stores states as files, replacing them atomically on every write
+#[derive(Clone, Copy, Debug, Default)]
+pub struct FileSystem {
+    durability: Durability
+}
+
+impl FileSystem {
+    pub fn new(durability: Durability) -> Self {
+        Self { durability }
+    }
+}
+
+impl StorageBackend for FileSystem {
+    fn load<'a>(&'a self, path: &'a Path) -> BoxFuture<'a, Result<Vec<u8>>> {
+        Box::pin(async move {
+            Ok(read(path).await?)
+        })
+    }
+
+    fn store<'a>(&'a self, path: &'a Path, bytes: &'a [u8]) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            let path = PathBuf::from(path);
+            create_dir_all_without_file_name(&path).await?;
+            write_atomic(&path, bytes, self.durability).await
+        })
+    }
+
+    fn exists<'a>(&'a self, path: &'a Path) -> BoxFuture<'a, Result<bool>> {
+        Box::pin(async move {
+            Ok(path.exists())
+        })
+    }
+
+    fn remove<'a>(&'a self, path: &'a Path) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            Ok(remove_file(path).await?)
+        })
+    }
+
+    fn list<'a>(&'a self, dir: &'a Path) -> BoxFuture<'a, Result<Vec<PathBuf>>> {
+        Box::pin(async move {
+            let mut paths = Vec::new();
+            let mut entries = read_dir(dir).await?;
+
+            while let Some(entry) = entries.next_entry().await? {
+                paths.push(entry.path());
+            }
+
+            Ok(paths)
+        })
+    }
+
+    fn rename<'a>(&'a self, from: &'a Path, to: &'a Path) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            Ok(rename(from, to).await?)
+        })
+    }
+
+    fn copy<'a>(&'a self, from: &'a Path, to: &'a Path) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            copy(from, to).await?;
+            Ok(())
+        })
+    }
+
+    fn is_local(&self) -> bool {
+        true
+    }
+}
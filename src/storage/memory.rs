@@ -0,0 +1,109 @@
+use std::{path::{Path, PathBuf}, collections::HashMap, sync::{Arc, Mutex}};
+
+use anyhow::{Result, anyhow};
+
+use super::{StorageBackend, BoxFuture};
+
+/// Keeps states in memory only, sharing the entries between clones
+#[derive(Clone, Debug, Default)]
+pub struct Memory {
+    entries: Arc<Mutex<HashMap<PathBuf, Vec<u8>>>>
+}
+
+impl Memory {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Copy of the bytes stored at `path`
+    pub fn get(&self, path: impl AsRef<Path>) -> Option<Vec<u8>> {
+        self.entries
+            .lock()
+            .ok()?
+            .get(path.as_ref())
+            .cloned()
+    }
+
+    pub fn insert(&self, path: impl AsRef<Path>, bytes: impl Into<Vec<u8>>) {
+        if let Ok(mut entries) = self.entries.lock() {
+            entries.insert(PathBuf::from(path.as_ref()), bytes.into());
+        }
+    }
+
+    fn lock(&self) -> Result<std::sync::MutexGuard<HashMap<PathBuf, Vec<u8>>>> {
+        self.entries
+            .lock()
+            .map_err(|_| anyhow!("Memory storage is poisoned"))
+    }
+}
+
+impl StorageBackend for Memory {
+    fn load<'a>(&'a self, path: &'a Path) -> BoxFuture<'a, Result<Vec<u8>>> {
+        Box::pin(async move {
+            self.lock()?
+                .get(path)
+                .cloned()
+                .ok_or_else(|| anyhow!("'{}' does not exist", path.display()))
+        })
+    }
+
+    fn store<'a>(&'a self, path: &'a Path, bytes: &'a [u8]) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            self.lock()?.insert(PathBuf::from(path), bytes.to_vec());
+            Ok(())
+        })
+    }
+
+    fn exists<'a>(&'a self, path: &'a Path) -> BoxFuture<'a, Result<bool>> {
+        Box::pin(async move {
+            Ok(self.lock()?.contains_key(path))
+        })
+    }
+
+    fn remove<'a>(&'a self, path: &'a Path) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            self.lock()?
+                .remove(path)
+                .map(|_| ())
+                .ok_or_else(|| anyhow!("'{}' does not exist", path.display()))
+        })
+    }
+
+    fn list<'a>(&'a self, dir: &'a Path) -> BoxFuture<'a, Result<Vec<PathBuf>>> {
+        Box::pin(async move {
+            Ok(self.lock()?
+                .keys()
+                .filter(|path| path.parent() == Some(dir))
+                .cloned()
+                .collect())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_entries_between_clones() {
+        tokio::runtime::Runtime::new().unwrap().block_on(async {
+            let memory = Memory::new();
+            let shared = memory.clone();
+            let path = Path::new("states/settings.toml");
+
+            assert!(!memory.exists(path).await.unwrap());
+            assert!(memory.load(path).await.is_err());
+
+            memory.store(path, b"volume = 3").await.unwrap();
+            assert_eq!(shared.load(path).await.unwrap(), b"volume = 3");
+            assert_eq!(shared.list(Path::new("states")).await.unwrap(), vec![PathBuf::from(path)]);
+
+            shared.rename(path, Path::new("states/renamed.toml")).await.unwrap();
+            assert!(!memory.exists(path).await.unwrap());
+            assert_eq!(memory.get("states/renamed.toml").unwrap(), b"volume = 3");
+
+            memory.remove(Path::new("states/renamed.toml")).await.unwrap();
+            assert!(shared.list(Path::new("states")).await.unwrap().is_empty());
+        });
+    }
+}
@@ -0,0 +1,55 @@
+pub(crate) mod file_system;
+pub(crate) mod memory;
+pub(crate) mod read_only;
+#[cfg(feature = "sqlite")]
+pub(crate) mod sqlite;
+#[cfg(feature = "redb")]
+pub(crate) mod redb_backend;
+
+use std::{path::{Path, PathBuf}, future::Future, pin::Pin};
+
+use anyhow::Result;
+
+pub use file_system::FileSystem;
+pub use memory::Memory;
+#[cfg(feature = "sqlite")]
+pub use sqlite::{Sqlite, SqliteDatabase};
+#[cfg(feature = "redb")]
+pub use redb_backend::{Redb, RedbDatabase, RedbLayout};
+
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// Where file states keep their bytes. Paths are absolute for the local file system
+/// and are otherwise the state's relative path, used as an opaque key.
+pub trait StorageBackend: Send + Sync + 'static {
+    fn load<'a>(&'a self, path: &'a Path) -> BoxFuture<'a, Result<Vec<u8>>>;
+
+    fn store<'a>(&'a self, path: &'a Path, bytes: &'a [u8]) -> BoxFuture<'a, Result<()>>;
+
+    fn exists<'a>(&'a self, path: &'a Path) -> BoxFuture<'a, Result<bool>>;
+
+    fn remove<'a>(&'a self, path: &'a Path) -> BoxFuture<'a, Result<()>>;
+
+    /// Paths of the entries directly inside `dir`
+    fn list<'a>(&'a self, dir: &'a Path) -> BoxFuture<'a, Result<Vec<PathBuf>>>;
+
+    fn rename<'a>(&'a self, from: &'a Path, to: &'a Path) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            let bytes = self.load(from).await?;
+            self.store(to, &bytes).await?;
+            self.remove(from).await
+        })
+    }
+
+    fn copy<'a>(&'a self, from: &'a Path, to: &'a Path) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            let bytes = self.load(from).await?;
+            self.store(to, &bytes).await
+        })
+    }
+
+    /// Whether paths are files on the local disk, which enables file locks and change watching
+    fn is_local(&self) -> bool {
+        false
+    }
+}
@@ -0,0 +1,58 @@
+use std::{path::{Path, PathBuf}, ops::Not};
+
+use anyhow::Result;
+
+use super::{StorageBackend, BoxFuture, FileSystem, Memory};
+
+/// Reads state files from disk but keeps every write in memory
+#[derive(Clone, Default)]
+pub(crate) struct ReadOnlyFiles {
+    files: FileSystem,
+    writes: Memory
+}
+
+impl StorageBackend for ReadOnlyFiles {
+    fn load<'a>(&'a self, path: &'a Path) -> BoxFuture<'a, Result<Vec<u8>>> {
+        Box::pin(async move {
+            match self.writes.exists(path).await? {
+                true => self.writes.load(path).await,
+                false => self.files.load(path).await,
+            }
+        })
+    }
+
+    fn store<'a>(&'a self, path: &'a Path, bytes: &'a [u8]) -> BoxFuture<'a, Result<()>> {
+        self.writes.store(path, bytes)
+    }
+
+    fn exists<'a>(&'a self, path: &'a Path) -> BoxFuture<'a, Result<bool>> {
+        Box::pin(async move {
+            Ok(self.writes.exists(path).await? || self.files.exists(path).await?)
+        })
+    }
+
+    fn remove<'a>(&'a self, path: &'a Path) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            self.writes.remove(path).await.ok();
+            Ok(())
+        })
+    }
+
+    fn list<'a>(&'a self, dir: &'a Path) -> BoxFuture<'a, Result<Vec<PathBuf>>> {
+        Box::pin(async move {
+            let mut paths = self.files.list(dir).await.unwrap_or_default();
+
+            for path in self.writes.list(dir).await? {
+                if paths.contains(&path).not() {
+                    paths.push(path);
+                }
+            }
+
+            Ok(paths)
+        })
+    }
+
+    fn is_local(&self) -> bool {
+        true
+    }
+}
@@ -0,0 +1,266 @@
+use std::{path::{Path, PathBuf}, sync::{Arc, OnceLock}, marker::PhantomData, fs::create_dir_all, collections::BTreeSet, ops::Not};
+
+use anyhow::{Result, Context, anyhow, bail};
+use redb::{Database, TableDefinition, ReadableTable, Table};
+use serde_json::{Value, Map};
+
+use crate::formats::{Format, header};
+
+use super::{StorageBackend, BoxFuture};
+
+const STATES: TableDefinition<&str, &[u8]> = TableDefinition::new("states");
+const FIELDS: TableDefinition<&str, &[u8]> = TableDefinition::new("fields");
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RedbLayout {
+    /// Store the encoded state under a single key
+    #[default]
+    Whole,
+    /// Store each top-level field under its own key, rewriting only the fields that changed.
+    /// Requires a self-describing format
+    PerField
+}
+
+/// Embedded key-value database shared between the states stored in it
+#[derive(Clone, Default)]
+pub struct RedbDatabase {
+    database: Arc<OnceLock<Database>>
+}
+
+impl RedbDatabase {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn open(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+
+        if let Some(dir) = path.parent() {
+            create_dir_all(dir)?;
+        }
+
+        let database = Database::create(path)
+            .with_context(|| format!("Failed to open '{}'", path.display()))?;
+
+        let transaction = database.begin_write()?;
+        transaction.open_table(STATES)?;
+        transaction.open_table(FIELDS)?;
+        transaction.commit()?;
+
+        self.database
+            .set(database)
+            .map_err(|_| anyhow!("Redb database is already open"))
+    }
+
+    pub fn backend<F>(&self, layout: RedbLayout) -> Redb<F>
+    where F: Format
+    {
+        Redb {
+            database: self.clone(),
+            layout,
+            format: PhantomData
+        }
+    }
+
+    fn get(&self) -> Result<&Database> {
+        self.database
+            .get()
+            .context("Redb database is not open")
+    }
+}
+
+/// Stores a state in a `RedbDatabase`, keyed by its path
+pub struct Redb<F> {
+    database: RedbDatabase,
+    layout: RedbLayout,
+    format: PhantomData<F>
+}
+
+impl<F> Clone for Redb<F> {
+    fn clone(&self) -> Self {
+        Self {
+            database: self.database.clone(),
+            layout: self.layout,
+            format: PhantomData
+        }
+    }
+}
+
+fn key(path: &Path) -> String {
+    path.to_string_lossy().into_owned()
+}
+
+/// Bounds of the field keys of a state, which are `<path>\0<field>`
+fn field_range(key: &str) -> (String, String) {
+    (format!("{key}\0"), format!("{key}\u{1}"))
+}
+
+fn field_keys(fields: &impl ReadableTable<&'static str, &'static [u8]>, key: &str) -> Result<Vec<String>> {
+    let (start, end) = field_range(key);
+    let mut keys = Vec::new();
+
+    for entry in fields.range::<&str>(start.as_str()..end.as_str())? {
+        let (field, _) = entry?;
+        keys.push(field.value().to_owned());
+    }
+
+    Ok(keys)
+}
+
+fn remove_fields(fields: &mut Table<&'static str, &'static [u8]>, key: &str) -> Result<()> {
+    for field in field_keys(fields, key)? {
+        fields.remove(field.as_str())?;
+    }
+
+    Ok(())
+}
+
+impl<F> Redb<F>
+where F: Format
+{
+    fn store_fields(&self, fields: &mut Table<&'static str, &'static [u8]>, key: &str, bytes: &[u8]) -> Result<()> {
+        let Value::Object(object) = F::decode::<Value>(header::unwrap::<F>(bytes)?)? else {
+            bail!("Only states serialized as maps can be stored per field");
+        };
+
+        let (start, _) = field_range(key);
+        let mut stale: BTreeSet<String> = field_keys(fields, key)?.into_iter().collect();
+
+        for (name, value) in object {
+            let field = format!("{start}{name}");
+            let value = serde_json::to_vec(&value)?;
+
+            stale.remove(&field);
+
+            let unchanged = fields
+                .get(field.as_str())?
+                .is_some_and(|existing| existing.value() == value.as_slice());
+
+            if unchanged {
+                continue;
+            }
+
+            fields.insert(field.as_str(), value.as_slice())?;
+        }
+
+        for field in stale {
+            fields.remove(field.as_str())?;
+        }
+
+        Ok(())
+    }
+}
+
+impl<F> StorageBackend for Redb<F>
+where F: Format
+{
+    fn load<'a>(&'a self, path: &'a Path) -> BoxFuture<'a, Result<Vec<u8>>> {
+        Box::pin(async move {
+            let key = key(path);
+            let transaction = self.database.get()?.begin_read()?;
+
+            if let Some(bytes) = transaction.open_table(STATES)?.get(key.as_str())? {
+                return Ok(bytes.value().to_vec());
+            }
+
+            let fields = transaction.open_table(FIELDS)?;
+            let (start, end) = field_range(&key);
+            let mut object = Map::new();
+
+            for entry in fields.range::<&str>(start.as_str()..end.as_str())? {
+                let (field, value) = entry?;
+                let name = field.value()[start.len()..].to_owned();
+                object.insert(name, serde_json::from_slice(value.value())?);
+            }
+
+            if object.is_empty() {
+                bail!("'{}' does not exist", path.display());
+            }
+
+            Ok(header::wrap::<F>(F::encode(&Value::Object(object))?))
+        })
+    }
+
+    fn store<'a>(&'a self, path: &'a Path, bytes: &'a [u8]) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            let key = key(path);
+            let transaction = self.database.get()?.begin_write()?;
+
+            {
+                let mut states = transaction.open_table(STATES)?;
+                let mut fields = transaction.open_table(FIELDS)?;
+
+                match self.layout {
+                    RedbLayout::Whole => {
+                        states.insert(key.as_str(), bytes)?;
+                        remove_fields(&mut fields, &key)?;
+                    },
+                    RedbLayout::PerField => {
+                        self.store_fields(&mut fields, &key, bytes)?;
+                        states.remove(key.as_str())?;
+                    },
+                }
+            }
+
+            transaction.commit()?;
+
+            Ok(())
+        })
+    }
+
+    fn exists<'a>(&'a self, path: &'a Path) -> BoxFuture<'a, Result<bool>> {
+        Box::pin(async move {
+            let key = key(path);
+            let transaction = self.database.get()?.begin_read()?;
+
+            if transaction.open_table(STATES)?.get(key.as_str())?.is_some() {
+                return Ok(true);
+            }
+
+            let fields = transaction.open_table(FIELDS)?;
+            let exists = field_keys(&fields, &key)?.is_empty().not();
+
+            Ok(exists)
+        })
+    }
+
+    fn remove<'a>(&'a self, path: &'a Path) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            let key = key(path);
+            let transaction = self.database.get()?.begin_write()?;
+
+            {
+                transaction.open_table(STATES)?.remove(key.as_str())?;
+                remove_fields(&mut transaction.open_table(FIELDS)?, &key)?;
+            }
+
+            transaction.commit()?;
+
+            Ok(())
+        })
+    }
+
+    fn list<'a>(&'a self, dir: &'a Path) -> BoxFuture<'a, Result<Vec<PathBuf>>> {
+        Box::pin(async move {
+            let transaction = self.database.get()?.begin_read()?;
+            let mut keys = BTreeSet::new();
+
+            for entry in transaction.open_table(STATES)?.iter()? {
+                let (key, _) = entry?;
+                keys.insert(key.value().to_owned());
+            }
+
+            for entry in transaction.open_table(FIELDS)?.iter()? {
+                let (key, _) = entry?;
+                let key = key.value();
+                keys.insert(key.split('\0').next().unwrap_or(key).to_owned());
+            }
+
+            Ok(keys
+                .into_iter()
+                .map(PathBuf::from)
+                .filter(|path| path.parent() == Some(dir))
+                .collect())
+        })
+    }
+}
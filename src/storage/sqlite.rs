@@ -0,0 +1,174 @@
+use std::{path::{Path, PathBuf}, sync::{Arc, Mutex, MutexGuard}, time::{SystemTime, UNIX_EPOCH}, fs::create_dir_all};
+
+use anyhow::{Result, Context, anyhow};
+use rusqlite::{Connection, OptionalExtension, params};
+
+use crate::formats::Format;
+
+use super::{StorageBackend, BoxFuture};
+
+/// Single database file holding one row per state, shared between the states stored in it
+#[derive(Clone, Default)]
+pub struct SqliteDatabase {
+    connection: Arc<Mutex<Option<Connection>>>
+}
+
+impl SqliteDatabase {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn open(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+
+        if let Some(dir) = path.parent() {
+            create_dir_all(dir)?;
+        }
+
+        let connection = Connection::open(path)
+            .with_context(|| format!("Failed to open '{}'", path.display()))?;
+
+        connection.execute(
+            "CREATE TABLE IF NOT EXISTS states (
+                key TEXT PRIMARY KEY,
+                format TEXT NOT NULL,
+                version INTEGER NOT NULL,
+                blob BLOB NOT NULL,
+                updated_at INTEGER NOT NULL
+            )",
+            []
+        )?;
+
+        *self.lock()? = Some(connection);
+
+        Ok(())
+    }
+
+    /// Storage for a state encoded with `F` at the given schema version
+    pub fn backend<F>(&self, version: u32) -> Sqlite
+    where F: Format
+    {
+        Sqlite {
+            database: self.clone(),
+            format: F::EXTENSION,
+            version
+        }
+    }
+
+    /// Runs `function` in a transaction, so the writes it makes either all land or none do
+    pub fn transaction(&self, function: impl FnOnce() -> Result<()>) -> Result<()> {
+        self.execute("BEGIN IMMEDIATE")?;
+
+        match function() {
+            Ok(()) => self.execute("COMMIT"),
+            Err(error) => {
+                self.execute("ROLLBACK")?;
+                Err(error).context("Rolled back the SQLite transaction")
+            },
+        }
+    }
+
+    fn execute(&self, statement: &str) -> Result<()> {
+        self.with(|connection| {
+            connection.execute_batch(statement)?;
+            Ok(())
+        })
+    }
+
+    fn lock(&self) -> Result<MutexGuard<Option<Connection>>> {
+        self.connection
+            .lock()
+            .map_err(|_| anyhow!("SQLite connection is poisoned"))
+    }
+
+    fn with<R>(&self, function: impl FnOnce(&Connection) -> Result<R>) -> Result<R> {
+        let lock = self.lock()?;
+        let connection = lock.as_ref().context("SQLite database is not open")?;
+        function(connection)
+    }
+}
+
+/// Stores a state as a row of a `SqliteDatabase`, keyed by its path
+#[derive(Clone)]
+pub struct Sqlite {
+    database: SqliteDatabase,
+    format: &'static str,
+    version: u32
+}
+
+fn key(path: &Path) -> String {
+    path.to_string_lossy().into_owned()
+}
+
+impl StorageBackend for Sqlite {
+    fn load<'a>(&'a self, path: &'a Path) -> BoxFuture<'a, Result<Vec<u8>>> {
+        Box::pin(async move {
+            self.database.with(|connection| {
+                connection
+                    .query_row("SELECT blob FROM states WHERE key = ?1", params![key(path)], |row| row.get(0))
+                    .optional()?
+                    .ok_or_else(|| anyhow!("'{}' does not exist", path.display()))
+            })
+        })
+    }
+
+    fn store<'a>(&'a self, path: &'a Path, bytes: &'a [u8]) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            let updated_at = SystemTime::now()
+                .duration_since(UNIX_EPOCH)?
+                .as_millis() as i64;
+
+            self.database.with(|connection| {
+                connection.execute(
+                    "INSERT INTO states (key, format, version, blob, updated_at) VALUES (?1, ?2, ?3, ?4, ?5)
+                    ON CONFLICT (key) DO UPDATE SET
+                        format = excluded.format,
+                        version = excluded.version,
+                        blob = excluded.blob,
+                        updated_at = excluded.updated_at",
+                    params![key(path), self.format, self.version, bytes, updated_at]
+                )?;
+                Ok(())
+            })
+        })
+    }
+
+    fn exists<'a>(&'a self, path: &'a Path) -> BoxFuture<'a, Result<bool>> {
+        Box::pin(async move {
+            self.database.with(|connection| {
+                let exists = connection
+                    .query_row("SELECT 1 FROM states WHERE key = ?1", params![key(path)], |_| Ok(()))
+                    .optional()?
+                    .is_some();
+                Ok(exists)
+            })
+        })
+    }
+
+    fn remove<'a>(&'a self, path: &'a Path) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            self.database.with(|connection| {
+                connection.execute("DELETE FROM states WHERE key = ?1", params![key(path)])?;
+                Ok(())
+            })
+        })
+    }
+
+    fn list<'a>(&'a self, dir: &'a Path) -> BoxFuture<'a, Result<Vec<PathBuf>>> {
+        Box::pin(async move {
+            self.database.with(|connection| {
+                let mut statement = connection.prepare("SELECT key FROM states")?;
+
+                let keys = statement
+                    .query_map([], |row| row.get::<_, String>(0))?
+                    .collect::<rusqlite::Result<Vec<_>>>()?;
+
+                Ok(keys
+                    .into_iter()
+                    .map(PathBuf::from)
+                    .filter(|path| path.parent() == Some(dir))
+                    .collect())
+            })
+        })
+    }
+}
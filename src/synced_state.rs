@@ -1,17 +1,79 @@
-use std::{borrow::Borrow, sync::{Arc}};
+use std::{borrow::Borrow, sync::{Arc, atomic::{AtomicU64, Ordering}}, marker::PhantomData};
 
 use serde::{Deserialize, Serialize};
 use tauri::{AppHandle, Manager};
 use tokio::{sync::{Mutex, MutexGuard}};
 
-#[derive(Clone, Debug)]
-pub struct Synced<T> {
+use crate::{registry::StateRegistry, deltas::Deltas};
+
+/// Payload of `synced-state://<key>-update` events. Versions increase by one with every update,
+/// so a listener can tell it missed one and call the `resync` command.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Versioned<T> {
+    pub key: String,
+    pub version: u64,
+    pub value: T
+}
+
+/// A state shared with the frontend. `Tag` tells apart several states of the same type,
+/// which Tauri could otherwise only manage once.
+#[derive(Debug)]
+pub struct Synced<T, Tag = ()> {
     pub(crate) key: String,
     pub(crate) state: Arc<Mutex<T>>,
-    pub(crate) handle: AppHandle
+    pub(crate) handle: AppHandle,
+    pub(crate) version: Arc<AtomicU64>,
+    pub(crate) tag: PhantomData<fn() -> Tag>
+}
+
+impl<T, Tag> Clone for Synced<T, Tag> {
+    fn clone(&self) -> Self {
+        Self {
+            key: self.key.clone(),
+            state: self.state.clone(),
+            handle: self.handle.clone(),
+            version: self.version.clone(),
+            tag: PhantomData
+        }
+    }
+}
+
+impl<S, Tag> Synced<S, Tag> {
+    pub(crate) fn versioned<V>(&self, value: V) -> Versioned<V> {
+        Versioned {
+            key: self.key.clone(),
+            version: self.version.load(Ordering::SeqCst),
+            value
+        }
+    }
+
+    /// Emits `value` under the next version. Called with the state locked, so versions follow the order of changes.
+    pub(crate) fn emit_versioned<V>(&self, value: V)
+    where V: Serialize + Clone
+    {
+        let key = &self.key;
+        let handle = &self.handle;
+        let event = format!("synced-state://{key}-update");
+
+        let version = self.version.fetch_add(1, Ordering::SeqCst) + 1;
+
+        if let Some(registry) = handle.try_state::<StateRegistry>() {
+            registry.notify(key, &value);
+        }
+
+        if let Some(deltas) = handle.try_state::<Deltas>() {
+            if deltas.emit(handle, key, version, &value) {
+                return;
+            }
+        }
+
+        handle
+            .emit_all(event.as_str(), Versioned { key: key.clone(), version, value })
+            .ok();
+    }
 }
 
-impl<T> Synced<T>
+impl<T, Tag> Synced<T, Tag>
 where T: Default + Serialize + for<'a> Deserialize<'a> + Clone
 {
     pub async fn init(
@@ -30,6 +92,8 @@ where T: Default + Serialize + for<'a> Deserialize<'a> + Clone
                 state
             )),
             handle: handle.clone(),
+            version: Arc::default(),
+            tag: PhantomData
         }
     }
 
@@ -43,13 +107,7 @@ where T: Default + Serialize + for<'a> Deserialize<'a> + Clone
     }
 
     fn emit_update(&self, payload: T) {
-        let key = &self.key;
-        let handle = &self.handle;
-        let event = format!("synced-state://{key}-update");
-
-        handle
-            .emit_all(event.as_str(), payload)
-            .ok();
+        self.emit_versioned(payload);
     }
 
     pub async fn mutate(
@@ -57,8 +115,44 @@ where T: Default + Serialize + for<'a> Deserialize<'a> + Clone
         function: impl FnOnce(&mut T)
     ) {
         let mut state = self.state.lock().await;
-
         function(&mut state);
+        self.mutated(&state);
+    }
+
+    /// Replaces the value with one computed from it, leaving it untouched when that fails
+    pub(crate) async fn try_update(&self, function: impl FnOnce(&T) -> anyhow::Result<T>) -> anyhow::Result<()> {
+        let mut state = self.state.lock().await;
+        *state = function(&state)?;
+        self.mutated(&state);
+        Ok(())
+    }
+
+    fn mutated(&self, state: &T) {
+        self.emit_update(state.to_owned());
+
+        #[cfg(feature = "ipc")]
+        self.publish(state);
+    }
+
+    #[cfg(feature = "ipc")]
+    fn publish(&self, value: &T) {
+        if let Some(sync) = self.handle.try_state::<crate::instance_sync::InstanceSync>() {
+            sync.publish(&self.key, value);
+        }
+    }
+
+    /// Replaces the value with one received from another instance, without publishing it back.
+    /// Skipped when `superseded` tells a local change ordered after it is still on its way.
+    #[cfg(feature = "ipc")]
+    pub(crate) async fn apply_remote(&self, value: T, superseded: impl FnOnce() -> bool) {
+        let mut state = self.state.lock().await;
+
+        // Checked under the lock, so a local change can't slip in between
+        if superseded() {
+            return;
+        }
+
+        *state = value;
 
         self.emit_update(state.to_owned());
     }
@@ -68,6 +162,12 @@ where T: Default + Serialize + for<'a> Deserialize<'a> + Clone
         lock.clone()
     }
 
+    /// The value along with the version of the last update emitted for it
+    pub async fn get_with_version(&self) -> Versioned<T> {
+        let lock = self.state.lock().await;
+        self.versioned(lock.clone())
+    }
+
     pub async fn set(&self, new_value: T) {
         self.mutate(|value| {
             *value = new_value.clone();
@@ -0,0 +1,492 @@
+use std::{borrow::Borrow, path::{Path, PathBuf}, sync::Arc, marker::PhantomData, ops::Not};
+
+use serde::{Serialize, Deserialize};
+use tauri::{AppHandle, Manager};
+use tokio::sync::{Mutex, MutexGuard, mpsc};
+use anyhow::{Result, Context, bail};
+use crate::{synced_state::{Synced, Versioned}, saveable_state::{Saveable, LoadReport, Unreadable}, formats::Format, options::FileOptions, utils::backups::backup_path, writer::Writer, dirty::DirtyFlag, options::{SavePolicy, LockPolicy}, file_lock::StateLock, portable::Portable, overrides::CollectedOverrides, utils::{merge::three_way, quarantine::quarantine}, migrations::NewerVersion};
+
+pub type SyncedFile<T, F, Tag = ()> = Synced<Saveable<T, F>, Tag>;
+
+impl<T, F, Tag> Synced<Saveable<T, F>, Tag>
+where T: Default + Serialize + for<'a> Deserialize<'a> + Clone + Send + Sync + 'static, F: Format, Tag: 'static
+{
+    pub async fn init(
+        key: impl Into<String>,
+        relative_path: impl AsRef<Path>,
+        options: FileOptions<T>,
+        handle: impl Borrow<AppHandle>
+    ) -> Result<Self> {
+
+        let handle = handle.borrow();
+        let key: String = key.into();
+
+        let mut options = match handle.try_state::<Portable>() {
+            Some(portable) => portable.apply(options),
+            None => options,
+        };
+
+        if let Some(collected) = handle.try_state::<CollectedOverrides>() {
+            options.overrides = collected.for_state(&key).map(Arc::new);
+        }
+
+        if let Some(resource) = options.defaults_resource.clone() {
+            let resource_path = handle.path_resolver()
+                .resolve_resource(&resource)
+                .with_context(|| format!("Failed to resolve resource '{}'", resource.display()))?;
+
+            let bytes = tokio::fs::read(&resource_path)
+                .await
+                .with_context(|| format!("Failed to read '{}'", resource_path.display()))?;
+
+            options.defaults = Some(Arc::new(Saveable::<T, F>::defaults_layer(&bytes)?));
+        }
+
+        let local = options.storage().is_local();
+
+        let mut path = match local {
+            true => options.base_dir.resolve(handle)?,
+            false => PathBuf::new(),
+        };
+
+        path.push(relative_path);
+
+        if local && path.extension().is_none() {
+            path.set_extension(F::EXTENSION);
+        }
+
+        Saveable::<T, F>::check_options(&options)?;
+
+        let mut state = match Saveable::<T, F>::load_path(&path, options.clone()).await {
+            Ok(state) => state,
+            // Starting from defaults would overwrite the newer file on the next save
+            Err(error) if error.downcast_ref::<NewerVersion>().is_some() => return Err(error),
+            Err(error) => {
+                eprintln!("Failed to load '{key}' state: {error:#}");
+
+                // See `commands::load_report`
+                emit_load_error(handle, &key, &error);
+
+                let unmoved = error.downcast_ref::<Unreadable>().is_some_and(|unreadable| unreadable.quarantined.is_none());
+
+                let mut state = Saveable::<T, F>::new(&path).with_options(options.clone());
+                state.report.load_error = Some(format!("{error:#}"));
+                state.state = options.recovery.recover(error, Saveable::<T, F>::defaults_of(&options)?)?;
+
+                if unmoved {
+                    quarantine(options.storage().as_ref(), path.clone(), options.quarantine_limit).await?;
+                }
+                state.file_lock = Arc::new(StateLock::acquire(&path, &options)?);
+                state
+            }
+        };
+
+        // See `commands::is_read_only`
+        if state.file_lock.is_read_only() {
+            eprintln!("'{key}' state is locked by another instance, changes will not be saved");
+
+            let event = format!("synced-state://{key}-lock-error");
+            handle
+                .emit_all(event.as_str(), format!("'{}' is locked by another instance", path.display()))
+                .ok();
+        }
+
+        let (merged_sender, mut merged_receiver) = mpsc::unbounded_channel();
+
+        state.dirty = DirtyFlag::new(&key, handle);
+        state.writer = Writer::spawn::<F>(
+            path.clone(),
+            options.clone(),
+            state.dirty.clone(),
+            state.fingerprint.clone(),
+            state.file_lock.clone(),
+            merged_sender
+        );
+
+        let applies_merges = options.lock_policy == LockPolicy::SharedWithReload && state.writer.is_some();
+
+        let synced = Self {
+            key,
+            state: Arc::new(Mutex::new(
+                state
+            )),
+            handle: handle.clone(),
+            version: Arc::default(),
+            tag: PhantomData
+        };
+
+        if applies_merges {
+            let synced = synced.clone();
+
+            tauri::async_runtime::spawn(async move {
+                while let Some((snapshot, merged)) = merged_receiver.recv().await {
+                    synced.apply_merge(snapshot, merged).await;
+                }
+            });
+        }
+
+        #[cfg(feature = "watch")]
+        if local && (options.watch || options.lock_policy == LockPolicy::SharedWithReload) {
+            synced.watch(&path)?;
+        }
+
+        Ok(synced)
+    }
+
+    #[cfg(feature = "watch")]
+    fn watch(&self, path: &Path) -> Result<()> {
+        let mut watcher = crate::watcher::FileWatcher::new(path)?;
+        let synced = self.clone();
+
+        tauri::async_runtime::spawn(async move {
+            while watcher.changed().await.is_some() {
+                synced.apply_external_change().await;
+            }
+        });
+
+        Ok(())
+    }
+
+    #[cfg(feature = "watch")]
+    async fn apply_external_change(&self) {
+        let mut lock = self.state.lock().await;
+
+        let Ok(bytes) = lock.options.storage().load(&lock.path).await else { return };
+
+        if lock.fingerprint.matches(&bytes) {
+            return;
+        }
+
+        // Unsaved changes are kept, `save` overwrites the file with them and `discard_changes` loads it instead.
+        // With `SharedWithReload` the next write merges both.
+        if lock.dirty.is_dirty() {
+            if lock.file_lock.policy() != LockPolicy::SharedWithReload {
+                eprintln!("'{}' state was changed externally while it had unsaved changes", self.key);
+
+                let event = format!("synced-state://{}-conflict", self.key);
+                self.handle
+                    .emit_all(event.as_str(), format!("'{}' was changed externally", lock.path.display()))
+                    .ok();
+            }
+            return;
+        }
+
+        match Saveable::<T, F>::decode(&bytes, &lock.options) {
+            Ok((value, report)) => {
+                if let Some(writer) = &lock.writer {
+                    writer.discard().await;
+                }
+
+                if lock.file_lock.policy() == LockPolicy::SharedWithReload {
+                    if let Ok(base) = serde_json::to_value(&value) {
+                        lock.file_lock.set_base(base);
+                    }
+                }
+
+                lock.state = value;
+                lock.report = report;
+                lock.fingerprint.set(crate::fingerprint::Fingerprint::of(&bytes));
+
+                let dirty = &lock.dirty;
+                dirty.saved(dirty.generation());
+
+                self.emit_update(lock.state.clone());
+            },
+            Err(error) => {
+                eprintln!("Ignoring external change to '{}' state: {error:#}", self.key);
+                emit_load_error(&self.handle, &self.key, &error);
+            },
+        }
+    }
+
+    /// Applies a merge the background writer made, keeping changes made since its snapshot
+    async fn apply_merge(&self, snapshot: T, merged: T) {
+        let mut lock = self.state.lock().await;
+
+        let values = (
+            serde_json::to_value(&snapshot),
+            serde_json::to_value(&lock.state),
+            serde_json::to_value(&merged)
+        );
+
+        let (Ok(base), Ok(ours), Ok(theirs)) = values else { return };
+
+        match serde_json::from_value::<T>(three_way(&base, ours, theirs)) {
+            Ok(value) => {
+                lock.state = value;
+
+                if let (Some(writer), true) = (&lock.writer, lock.dirty.is_dirty()) {
+                    writer.changed(lock.state.clone(), lock.dirty.changed());
+                }
+
+                self.emit_update(lock.state.clone());
+            },
+            Err(error) => {
+                eprintln!("Failed to apply merged '{}' state: {error:#}", self.key);
+            },
+        }
+    }
+
+    pub fn init_sync(
+        key: impl Into<String>,
+        relative_path: impl AsRef<Path>,
+        options: FileOptions<T>,
+        handle: impl Borrow<AppHandle>
+    ) -> Result<Self> {
+        tokio::task::block_in_place(|| {
+            tauri::async_runtime::block_on(Self::init(key, relative_path, options, handle))
+        })
+    }
+
+    fn emit_update(&self, payload: T) {
+        self.emit_versioned(payload);
+    }
+
+    pub async fn mutate(
+        &self,
+        function: impl FnOnce(&mut T)
+    ) {
+        let mut lock = self.state.lock().await;
+        function(&mut lock.state);
+        self.mutated(lock).await;
+    }
+
+    /// Replaces the value with one computed from it, leaving it untouched when that fails
+    pub(crate) async fn try_update(&self, function: impl FnOnce(&T) -> Result<T>) -> Result<()> {
+        let mut lock = self.state.lock().await;
+        lock.state = function(&lock.state)?;
+        self.mutated(lock).await;
+        Ok(())
+    }
+
+    /// Emits and saves the value changed under `lock`
+    async fn mutated(&self, mut lock: MutexGuard<'_, Saveable<T, F>>) {
+        self.emit_update(lock.state.clone());
+
+        let dirty = lock.dirty.clone();
+
+        match (&lock.writer, lock.options.save_policy) {
+            (Some(writer), _) => writer.changed(lock.state.clone(), dirty.changed()),
+            (None, SavePolicy::Manual) => { dirty.changed(); },
+            (None, _) => match lock.persist().await {
+                Ok(merged) => {
+                    dirty.saved(dirty.generation());
+                    if merged {
+                        self.emit_update(lock.state.clone());
+                    }
+                },
+                Err(_) => { dirty.changed(); },
+            },
+        }
+    }
+
+    pub async fn save(&self) -> Result<()> {
+        let mut lock = self.state.lock().await;
+        let dirty = lock.dirty.clone();
+        let generation = dirty.generation();
+
+        match lock.writer.clone() {
+            Some(writer) => {
+                let snapshot = lock.state.clone();
+                drop(lock);
+                writer.flush(snapshot, generation).await
+            },
+            None => {
+                let merged = lock.persist().await?;
+                dirty.saved(generation);
+                if merged {
+                    self.emit_update(lock.state.clone());
+                }
+                Ok(())
+            },
+        }
+    }
+
+    /// Writes the state without marking it saved, returning what marks it once the write is committed
+    pub(crate) async fn save_staged(&self) -> Result<impl FnOnce() + Send> {
+        let mut lock = self.state.lock().await;
+        let dirty = lock.dirty.clone();
+        let generation = dirty.generation();
+
+        let (merged, staged) = lock.persist_staged().await?;
+        if merged {
+            self.emit_update(lock.state.clone());
+        }
+
+        let fingerprint = lock.fingerprint.clone();
+        Ok(move || {
+            fingerprint.set(staged);
+            dirty.saved(generation);
+        })
+    }
+
+    pub async fn reload(&self) -> Result<()> {
+        let mut lock = self.state.lock().await;
+
+        if let Some(writer) = &lock.writer {
+            writer.discard().await;
+        }
+
+        let loaded = Saveable::<T, F>::load_locked(&lock.path, lock.options.clone(), lock.file_lock.clone()).await?;
+        lock.state = loaded.state;
+        lock.report = loaded.report;
+        lock.fingerprint.set(loaded.fingerprint.get());
+
+        let dirty = &lock.dirty;
+        dirty.saved(dirty.generation());
+
+        self.emit_update(lock.state.clone());
+
+        Ok(())
+    }
+
+    pub async fn discard_changes(&self) -> Result<()> {
+        self.reload().await
+    }
+
+    pub async fn is_dirty(&self) -> bool {
+        self.state
+            .lock()
+            .await
+            .dirty
+            .is_dirty()
+    }
+
+    /// Whether another instance holds the exclusive lock on the file, so changes are not saved
+    pub async fn is_read_only(&self) -> bool {
+        self.state
+            .lock()
+            .await
+            .file_lock
+            .is_read_only()
+    }
+
+    pub fn save_sync(&self) -> Result<()> {
+        tokio::task::block_in_place(|| {
+            tauri::async_runtime::block_on(self.save())
+        })
+    }
+
+    pub(crate) fn is_dirty_sync(&self) -> bool {
+        tokio::task::block_in_place(|| {
+            tauri::async_runtime::block_on(self.is_dirty())
+        })
+    }
+
+    pub(crate) fn save_staged_sync(&self) -> Result<impl FnOnce() + Send> {
+        tokio::task::block_in_place(|| {
+            tauri::async_runtime::block_on(self.save_staged())
+        })
+    }
+
+    pub async fn get(&self) -> T {
+        let lock = self.state.lock().await;
+        lock.state.clone()
+    }
+
+    /// The value along with the version of the last update emitted for it
+    pub async fn get_with_version(&self) -> Versioned<T> {
+        let lock = self.state.lock().await;
+        self.versioned(lock.state.clone())
+    }
+
+    pub async fn set(&self, new_value: T) {
+        self.mutate(|value| {
+            *value = new_value.clone();
+        }).await;
+    }
+
+    pub async fn lock(&self) -> MutexGuard<Saveable<T, F>> {
+        self.state.lock().await
+    }
+
+    pub async fn reset(&self) {
+        let defaults = self.state
+            .lock()
+            .await
+            .defaults()
+            .unwrap_or_default();
+
+        self.set(defaults).await;
+    }
+
+    /// Dotted paths of fields set from environment variables or command line arguments,
+    /// which are not saved and should be shown as locked
+    pub async fn overridden_fields(&self) -> Vec<String> {
+        self.state
+            .lock()
+            .await
+            .options
+            .overrides
+            .as_ref()
+            .map(|overrides| overrides.fields())
+            .unwrap_or_default()
+    }
+
+    /// Resets the field at a dotted path, which drops it from a file layered over bundled defaults
+    pub async fn reset_field(&self, field: &str) -> Result<()> {
+        let lock = self.state.lock().await;
+
+        let defaults = serde_json::to_value(lock.defaults()?)?;
+        let mut value = serde_json::to_value(&lock.state)?;
+
+        let default = field
+            .split('.')
+            .try_fold(&defaults, |value, key| value.get(key))
+            .with_context(|| format!("'{}' state has no field '{field}'", self.key))?;
+
+        let target = field
+            .split('.')
+            .try_fold(&mut value, |value, key| value.get_mut(key))
+            .with_context(|| format!("'{}' state has no field '{field}'", self.key))?;
+
+        *target = default.clone();
+
+        let state = serde_json::from_value::<T>(value)?;
+        drop(lock);
+
+        self.set(state).await;
+
+        Ok(())
+    }
+
+    pub async fn load_report(&self) -> LoadReport {
+        self.state
+            .lock()
+            .await
+            .load_report()
+            .clone()
+    }
+
+    pub async fn list_backups(&self) -> Vec<PathBuf> {
+        self.state
+            .lock()
+            .await
+            .backup_paths()
+            .await
+    }
+
+    pub async fn restore_backup(&self, index: usize) -> Result<()> {
+        let (backup, options) = {
+            let lock = self.state.lock().await;
+            (backup_path(&lock.path, index), lock.options.clone())
+        };
+
+        if index == 0 || options.storage().exists(&backup).await?.not() {
+            bail!("Backup {index} of '{}' state does not exist", self.key);
+        }
+
+        let value = Saveable::<T, F>::read_value(&backup, &options).await?;
+        self.set(value).await;
+
+        Ok(())
+    }
+}
+
+fn emit_load_error(handle: &AppHandle, key: &str, error: &anyhow::Error) {
+    let event = format!("synced-state://{key}-load-error");
+
+    handle
+        .emit_all(event.as_str(), format!("{error:#}"))
+        .ok();
+}
@@ -0,0 +1,91 @@
+use std::{path::PathBuf, borrow::Borrow, ffi::OsString, ops::Not};
+
+use anyhow::Result;
+
+use crate::storage::StorageBackend;
+
+pub fn backup_path(file_path: impl Borrow<PathBuf>, index: usize) -> PathBuf {
+    let path = file_path.borrow();
+
+    let mut name = path.file_name()
+        .map(OsString::from)
+        .unwrap_or_default();
+    name.push(format!(".bak.{index}"));
+
+    path.with_file_name(name)
+}
+
+pub fn pre_migration_path(file_path: impl Borrow<PathBuf>, version: u32) -> PathBuf {
+    let path = file_path.borrow();
+
+    let mut name = path.file_name()
+        .map(OsString::from)
+        .unwrap_or_default();
+    name.push(format!(".v{version}.bak"));
+
+    path.with_file_name(name)
+}
+
+pub async fn rotate_backups(
+    backend: &dyn StorageBackend,
+    file_path: impl Borrow<PathBuf>,
+    count: usize
+) -> Result<()> {
+    let path = file_path.borrow();
+
+    if count == 0 || backend.exists(path).await?.not() {
+        return Ok(());
+    }
+
+    let oldest = backup_path(path, count);
+    if backend.exists(&oldest).await? {
+        backend.remove(&oldest).await?;
+    }
+
+    for index in (1..count).rev() {
+        let from = backup_path(path, index);
+        if backend.exists(&from).await? {
+            backend.rename(&from, &backup_path(path, index + 1)).await?;
+        }
+    }
+
+    backend.copy(path, &backup_path(path, 1)).await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::Memory;
+
+    #[test]
+    fn rotates_backups_up_to_the_count() {
+        tokio::runtime::Runtime::new().unwrap().block_on(async {
+            let memory = Memory::new();
+            let path = PathBuf::from("states/settings.toml");
+
+            for content in ["first", "second", "third", "fourth"] {
+                memory.insert(&path, content);
+                rotate_backups(&memory, &path, 2).await.unwrap();
+            }
+
+            assert_eq!(memory.get(backup_path(&path, 1)).unwrap(), b"fourth");
+            assert_eq!(memory.get(backup_path(&path, 2)).unwrap(), b"third");
+            assert!(memory.get(backup_path(&path, 3)).is_none());
+            assert_eq!(memory.get(&path).unwrap(), b"fourth");
+        });
+    }
+
+    #[test]
+    fn skips_missing_files() {
+        tokio::runtime::Runtime::new().unwrap().block_on(async {
+            let memory = Memory::new();
+            let path = PathBuf::from("states/settings.toml");
+
+            rotate_backups(&memory, &path, 2).await.unwrap();
+
+            assert!(memory.get(backup_path(&path, 1)).is_none());
+        });
+    }
+}
@@ -0,0 +1,145 @@
+use std::ops::Not;
+
+use serde_json::{Value, Map};
+
+pub fn merge(base: &mut Value, overlay: Value) {
+    match (base, overlay) {
+        (Value::Object(base), Value::Object(overlay)) => {
+            for (key, value) in overlay {
+                match base.get_mut(&key) {
+                    Some(existing) => merge(existing, value),
+                    None => { base.insert(key, value); },
+                }
+            }
+        },
+        (base, overlay) => *base = overlay,
+    }
+}
+
+/// Parts of `value` that differ from `base`, or `None` when they are equal
+pub fn changed(base: &Value, value: Value) -> Option<Value> {
+    if &value == base {
+        return None;
+    }
+
+    match (base, value) {
+        (Value::Object(base), Value::Object(value)) => {
+            let changed = value
+                .into_iter()
+                .filter_map(|(key, value)| match base.get(&key) {
+                    Some(base) => changed(base, value).map(|value| (key, value)),
+                    None => Some((key, value)),
+                })
+                .collect();
+
+            Some(Value::Object(changed))
+        },
+        (_, value) => Some(value),
+    }
+}
+
+/// Dotted paths of keys present in `base` but not in `other`
+pub fn missing_keys(base: &Value, other: &Value) -> Vec<String> {
+    let mut missing = Vec::new();
+    collect_missing_keys(base, other, "", &mut missing);
+    missing
+}
+
+fn collect_missing_keys(base: &Value, other: &Value, prefix: &str, missing: &mut Vec<String>) {
+    let (Value::Object(base), Value::Object(other)) = (base, other) else { return };
+
+    for (key, value) in base {
+        let path = if prefix.is_empty() {
+            key.clone()
+        } else {
+            format!("{prefix}.{key}")
+        };
+
+        match other.get(key) {
+            Some(other_value) => collect_missing_keys(value, other_value, &path, missing),
+            None => missing.push(path),
+        }
+    }
+}
+
+/// Combines changes made in `ours` and `theirs` since `base`, preferring `ours` on conflicts
+pub fn three_way(base: &Value, ours: Value, theirs: Value) -> Value {
+    if &ours == base {
+        return theirs;
+    }
+    if &theirs == base || ours == theirs {
+        return ours;
+    }
+
+    match (base, ours, theirs) {
+        (Value::Object(base), Value::Object(mut ours), Value::Object(mut theirs)) => {
+            let mut keys: Vec<String> = ours.keys().cloned().collect();
+            keys.extend(theirs.keys().filter(|key| ours.contains_key(*key).not()).cloned());
+
+            let mut merged = Map::new();
+
+            for key in keys {
+                let value = match (base.get(&key), ours.remove(&key), theirs.remove(&key)) {
+                    (Some(base), Some(ours), Some(theirs)) => Some(three_way(base, ours, theirs)),
+                    (None, Some(ours), Some(theirs)) => Some(three_way(&Value::Null, ours, theirs)),
+                    (Some(base), Some(ours), None) => (&ours != base).then_some(ours),
+                    (Some(base), None, Some(theirs)) => (&theirs != base).then_some(theirs),
+                    (None, ours, theirs) => ours.or(theirs),
+                    (Some(_), None, None) => None,
+                };
+
+                if let Some(value) = value {
+                    merged.insert(key, value);
+                }
+            }
+
+            Value::Object(merged)
+        },
+        (_, ours, _) => ours,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn merges_nested_objects() {
+        let mut base = json!({ "volume": 3, "window": { "width": 800, "height": 600 } });
+        merge(&mut base, json!({ "window": { "width": 1024 }, "theme": "dark" }));
+
+        assert_eq!(base, json!({ "volume": 3, "window": { "width": 1024, "height": 600 }, "theme": "dark" }));
+    }
+
+    #[test]
+    fn finds_missing_keys() {
+        let base = json!({ "volume": 3, "window": { "width": 800, "height": 600 } });
+
+        assert_eq!(missing_keys(&base, &json!({ "window": { "width": 1024 } })), vec!["volume", "window.height"]);
+        assert!(missing_keys(&base, &base).is_empty());
+    }
+
+    #[test]
+    fn combines_both_sides_preferring_ours() {
+        let base = json!({ "volume": 3, "theme": "light", "window": { "width": 800 }, "removed": true });
+        let ours = json!({ "volume": 5, "theme": "dark", "window": { "width": 800 } });
+        let theirs = json!({ "volume": 3, "theme": "blue", "window": { "width": 1024 }, "removed": true, "added": 1 });
+
+        assert_eq!(
+            three_way(&base, ours, theirs),
+            json!({ "volume": 5, "theme": "dark", "window": { "width": 1024 }, "added": 1 })
+        );
+    }
+
+    #[test]
+    fn keeps_only_changed_fields() {
+        let base = json!({ "volume": 3, "window": { "width": 800, "height": 600 } });
+
+        assert_eq!(changed(&base, base.clone()), None);
+        assert_eq!(
+            changed(&base, json!({ "volume": 3, "window": { "width": 1024, "height": 600 } })),
+            Some(json!({ "window": { "width": 1024 } }))
+        );
+    }
+}
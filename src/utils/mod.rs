@@ -1 +1,5 @@
-pub(crate) mod create_dir_all_without_file_name;
\ No newline at end of file
+pub(crate) mod create_dir_all_without_file_name;
+pub(crate) mod write_atomic;
+pub(crate) mod quarantine;
+pub(crate) mod backups;
+pub(crate) mod merge;
@@ -0,0 +1,78 @@
+use std::{path::PathBuf, borrow::Borrow, ffi::OsString, time::{SystemTime, UNIX_EPOCH}};
+
+use anyhow::{Result, Context};
+
+use crate::storage::StorageBackend;
+
+pub async fn quarantine(
+    backend: &dyn StorageBackend,
+    file_path: impl Borrow<PathBuf>,
+    limit: usize
+) -> Result<PathBuf> {
+    let path = file_path.borrow();
+
+    let file_name = path.file_name()
+        .map(OsString::from)
+        .context("State path has no file name")?;
+
+    let mut prefix = file_name.clone();
+    prefix.push(".corrupt-");
+
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)?
+        .as_millis();
+
+    let mut quarantined_name = prefix.clone();
+    quarantined_name.push(timestamp.to_string());
+    let quarantined_path = path.with_file_name(quarantined_name);
+
+    backend.rename(path, &quarantined_path).await?;
+
+    let prefix = prefix.to_string_lossy().into_owned();
+    let mut quarantined = Vec::new();
+
+    if let Some(dir) = path.parent() {
+        for entry in backend.list(dir).await? {
+            let Some(name) = entry.file_name() else { continue };
+            let name = name.to_string_lossy();
+
+            let Some(timestamp) = name.strip_prefix(&prefix) else { continue };
+            let Ok(timestamp) = timestamp.parse::<u128>() else { continue };
+
+            quarantined.push((timestamp, entry.clone()));
+        }
+    }
+
+    quarantined.sort_by(|a, b| b.0.cmp(&a.0));
+
+    for (_, stale) in quarantined.into_iter().skip(limit) {
+        backend.remove(&stale).await.ok();
+    }
+
+    Ok(quarantined_path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::Memory;
+
+    #[test]
+    fn moves_the_file_aside_and_keeps_the_newest() {
+        tokio::runtime::Runtime::new().unwrap().block_on(async {
+            let memory = Memory::new();
+            let path = PathBuf::from("states/settings.toml");
+
+            memory.insert("states/settings.toml.corrupt-1", "oldest");
+            memory.insert("states/settings.toml.corrupt-2", "older");
+            memory.insert(&path, "broken");
+
+            let quarantined = quarantine(&memory, &path, 2).await.unwrap();
+
+            assert!(memory.get(&path).is_none());
+            assert_eq!(memory.get(quarantined).unwrap(), b"broken");
+            assert_eq!(memory.get("states/settings.toml.corrupt-2").unwrap(), b"older");
+            assert!(memory.get("states/settings.toml.corrupt-1").is_none());
+        });
+    }
+}
@@ -0,0 +1,43 @@
+use std::{path::PathBuf, borrow::Borrow, ffi::OsString};
+
+use anyhow::Result;
+use tokio::{fs::{File, rename}, io::AsyncWriteExt};
+
+use crate::options::Durability;
+
+pub async fn write_atomic(
+    file_path: impl Borrow<PathBuf>,
+    bytes: &[u8],
+    durability: Durability
+) -> Result<()> {
+    let path = file_path.borrow();
+
+    let mut temp_name = path.file_name()
+        .map(OsString::from)
+        .unwrap_or_default();
+    temp_name.push(".tmp");
+    let temp_path = path.with_file_name(temp_name);
+
+    let mut file = File::create(&temp_path).await?;
+    file.write_all(bytes).await?;
+    file.flush().await?;
+
+    match durability {
+        Durability::None => {},
+        Durability::Flush => file.sync_data().await?,
+        Durability::Full => file.sync_all().await?,
+    }
+
+    drop(file);
+
+    rename(&temp_path, path).await?;
+
+    #[cfg(unix)]
+    if durability == Durability::Full {
+        if let Some(dir) = path.parent() {
+            File::open(dir).await?.sync_all().await?;
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,56 @@
+use std::{path::Path, time::Duration};
+
+use notify::{RecommendedWatcher, RecursiveMode, Watcher, Event};
+use tokio::sync::mpsc;
+use anyhow::{Result, Context};
+
+/// Watches the directory of a state file, since atomic writes replace the file itself
+pub(crate) struct FileWatcher {
+    _watcher: RecommendedWatcher,
+    receiver: mpsc::UnboundedReceiver<()>
+}
+
+impl FileWatcher {
+    pub fn new(path: &Path) -> Result<Self> {
+        let (sender, receiver) = mpsc::unbounded_channel();
+
+        let file_name = path.file_name()
+            .context("State path has no file name")?
+            .to_owned();
+        let dir = path.parent()
+            .context("State path has no parent directory")?;
+
+        let mut watcher = notify::recommended_watcher(move |event: notify::Result<Event>| {
+            let Ok(event) = event else { return };
+
+            if event.kind.is_access() {
+                return;
+            }
+
+            let touches_file = event.paths
+                .iter()
+                .any(|path| path.file_name() == Some(file_name.as_os_str()));
+
+            if touches_file {
+                sender.send(()).ok();
+            }
+        })?;
+
+        watcher.watch(dir, RecursiveMode::NonRecursive)?;
+
+        Ok(Self {
+            _watcher: watcher,
+            receiver
+        })
+    }
+
+    /// Waits for the next change, coalescing bursts of events from a single save
+    pub async fn changed(&mut self) -> Option<()> {
+        self.receiver.recv().await?;
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        while self.receiver.try_recv().is_ok() {}
+
+        Some(())
+    }
+}
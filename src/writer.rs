@@ -0,0 +1,166 @@
+use std::{path::PathBuf, sync::Arc};
+
+use serde::{Serialize, Deserialize};
+use tokio::{sync::{mpsc, oneshot}, time::{Instant, timeout_at}};
+use anyhow::{Result, anyhow};
+
+use crate::{saveable_state::Saveable, formats::Format, options::{FileOptions, SavePolicy}, dirty::DirtyFlag, fingerprint::Fingerprint, file_lock::StateLock};
+
+enum WriterMessage<T> {
+    Changed(T, u64),
+    Flush(T, u64, oneshot::Sender<Result<()>>),
+    Discard(oneshot::Sender<()>)
+}
+
+/// Background task that coalesces snapshots of a file state and writes them off-lock
+pub(crate) struct Writer<T> {
+    sender: mpsc::UnboundedSender<WriterMessage<T>>
+}
+
+impl<T> Clone for Writer<T> {
+    fn clone(&self) -> Self {
+        Self {
+            sender: self.sender.clone()
+        }
+    }
+}
+
+impl<T> Writer<T>
+where T: Default + Serialize + for<'a> Deserialize<'a> + Send + Sync + 'static
+{
+    /// Spawns a writer for background save policies, `None` for the others.
+    /// Snapshots merged with another instance's changes are sent to `merged` alongside the merge result.
+    pub fn spawn<F>(
+        path: PathBuf,
+        options: FileOptions<T>,
+        dirty: DirtyFlag,
+        fingerprint: Fingerprint,
+        file_lock: Arc<StateLock>,
+        merged: mpsc::UnboundedSender<(T, T)>
+    ) -> Option<Self>
+    where F: Format
+    {
+        let (SavePolicy::Debounced(_) | SavePolicy::Interval(_)) = options.save_policy else {
+            return None;
+        };
+
+        let (sender, receiver) = mpsc::unbounded_channel();
+
+        tauri::async_runtime::spawn(
+            run::<T, F>(receiver, path, options, dirty, fingerprint, file_lock, merged)
+        );
+
+        Some(Self { sender })
+    }
+
+    pub fn changed(&self, snapshot: T, generation: u64) {
+        self.sender
+            .send(WriterMessage::Changed(snapshot, generation))
+            .ok();
+    }
+
+    pub async fn flush(&self, snapshot: T, generation: u64) -> Result<()> {
+        let (reply, response) = oneshot::channel();
+
+        self.sender
+            .send(WriterMessage::Flush(snapshot, generation, reply))
+            .map_err(|_| anyhow!("State writer has stopped"))?;
+
+        response.await?
+    }
+
+    /// Drops any pending write, returning once in-flight writes are done
+    pub async fn discard(&self) {
+        let (reply, response) = oneshot::channel();
+
+        if self.sender.send(WriterMessage::Discard(reply)).is_ok() {
+            response.await.ok();
+        }
+    }
+}
+
+async fn run<T, F>(
+    mut receiver: mpsc::UnboundedReceiver<WriterMessage<T>>,
+    path: PathBuf,
+    options: FileOptions<T>,
+    dirty: DirtyFlag,
+    fingerprint: Fingerprint,
+    file_lock: Arc<StateLock>,
+    merged: mpsc::UnboundedSender<(T, T)>
+)
+where T: Default + Serialize + for<'a> Deserialize<'a> + Send + Sync + 'static, F: Format
+{
+    let write = |snapshot: T, generation: u64| {
+        let path = &path;
+        let options = &options;
+        let dirty = &dirty;
+        let fingerprint = &fingerprint;
+        let file_lock = &file_lock;
+        let merged = &merged;
+        async move {
+            let result = Saveable::<T, F>::write(path, options, &snapshot, file_lock, fingerprint).await?;
+            dirty.saved(generation);
+            if let Some(result) = result {
+                merged.send((snapshot, result)).ok();
+            }
+            Ok::<(), anyhow::Error>(())
+        }
+    };
+
+    let mut pending: Option<(T, u64)> = None;
+    let mut first_change = Instant::now();
+    let mut last_change = Instant::now();
+
+    loop {
+        let message = if pending.is_some() {
+            let mut deadline = match options.save_policy {
+                SavePolicy::Debounced(window) => last_change + window,
+                SavePolicy::Interval(period) => first_change + period,
+                SavePolicy::OnEveryMutation | SavePolicy::Manual => last_change,
+            };
+
+            if let Some(max_delay) = options.max_delay {
+                deadline = deadline.min(first_change + max_delay);
+            }
+
+            match timeout_at(deadline, receiver.recv()).await {
+                Ok(message) => message,
+                Err(_) => {
+                    if let Some((snapshot, generation)) = pending.take() {
+                        if let Err(error) = write(snapshot, generation).await {
+                            eprintln!("Error while saving state: {error}");
+                        }
+                    }
+                    continue;
+                },
+            }
+        } else {
+            receiver.recv().await
+        };
+
+        match message {
+            Some(WriterMessage::Changed(snapshot, generation)) => {
+                let now = Instant::now();
+                if pending.is_none() {
+                    first_change = now;
+                }
+                last_change = now;
+                pending = Some((snapshot, generation));
+            },
+            Some(WriterMessage::Flush(snapshot, generation, reply)) => {
+                pending = None;
+                reply.send(write(snapshot, generation).await).ok();
+            },
+            Some(WriterMessage::Discard(reply)) => {
+                pending = None;
+                reply.send(()).ok();
+            },
+            None => {
+                if let Some((snapshot, generation)) = pending.take() {
+                    write(snapshot, generation).await.ok();
+                }
+                break;
+            },
+        }
+    }
+}